@@ -34,6 +34,11 @@ fn generate_config_docs() {
     let mut output = String::new();
 
     for field in &my_struct.fields {
+        // Fields serde skips are not config keys
+        if is_serde_skipped(&field.attrs) {
+            continue;
+        }
+
         // Field name (unwrap because struct fields must be named)
         let field_name = field.ident.as_ref().unwrap().to_string();
 
@@ -66,6 +71,13 @@ fn type_to_string(ty: &Type) -> String {
     ty.to_token_stream().to_string().replace(" ", "")
 }
 
+// Helper function to check for #[serde(skip)]
+fn is_serde_skipped(attrs: &[Attribute]) -> bool {
+    attrs
+        .iter()
+        .any(|attr| attr.path.is_ident("serde") && attr.tokens.to_string() == "(skip)")
+}
+
 // Helper function to extract doc comments (///) from attributes
 fn extract_doc_comments(attrs: &[Attribute]) -> String {
     let mut doc_lines = vec![];
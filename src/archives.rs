@@ -0,0 +1,619 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder};
+use tar::{EntryType, Header};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    commands::command::{Result, error},
+    config::Config,
+    globals::FOLDR_MANIFEST_FILE,
+    zip::{ArchiveLimits, LimitedReader, ZipUtil},
+};
+
+/// Kinds of archives accepted as template sources, detected from their first bytes
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    pub fn name(&self) -> &str {
+        return match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        };
+    }
+}
+
+/// Converts archives that were not created by foldr, like release tarballs, into template files.
+/// A manifest is generated for them when they are stored or spawned, like for any template file
+/// without one
+pub struct ForeignArchive;
+
+impl ForeignArchive {
+    /// Detect the format of an archive from its magic bytes. The stream is rewound afterwards
+    pub fn detect<R: Read + Seek>(stream: &mut R) -> Result<ArchiveFormat> {
+        let mut header = Vec::with_capacity(512);
+        stream
+            .by_ref()
+            .take(512)
+            .read_to_end(&mut header)
+            .map_err(|_| error("IO error reading archive"))?;
+        stream
+            .seek(SeekFrom::Start(0))
+            .map_err(|_| error("IO error reading archive"))?;
+        return Self::detect_header(&header);
+    }
+
+    /// Detect the format of an archive from its first 512 bytes
+    fn detect_header(header: &[u8]) -> Result<ArchiveFormat> {
+        return if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+            Ok(ArchiveFormat::Zip)
+        } else if header.starts_with(&[0x1f, 0x8b]) {
+            Ok(ArchiveFormat::TarGz)
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Ok(ArchiveFormat::TarZst)
+        } else if header.get(257..262) == Some(b"ustar") {
+            Ok(ArchiveFormat::Tar)
+        } else {
+            Err(error(
+                "File is not a template file, or a zip, tar, tar.gz or tar.zst archive",
+            ))
+        };
+    }
+
+    /// Convert an archive into a template file, removing the single directory all entries are in when
+    /// [`strip_top_level`] is set. Returns None when the archive is a zip file that can be used as is
+    pub fn to_template_file(
+        config: &Config,
+        mut file: &File,
+        strip_top_level: bool,
+    ) -> Result<Option<File>> {
+        let format = Self::detect(&mut file)?;
+        if format == ArchiveFormat::Zip && !strip_top_level {
+            return Ok(None);
+        }
+        let limits = ArchiveLimits::from_config(config);
+        return Self::write_template(format, |writer| match format {
+            ArchiveFormat::Zip => Self::strip_zip(file, writer, &limits),
+            _ => Self::convert_tar(format, file, writer, strip_top_level, &limits),
+        })
+        .map(Some);
+    }
+
+    /// Convert an archive read from a stream that can't seek, like stdin, into a template file.
+    /// Tar archives are converted while they are read, zip files are copied to a temporary file first
+    pub fn from_stream<R: Read>(
+        config: &Config,
+        mut stream: R,
+        strip_top_level: bool,
+    ) -> Result<File> {
+        let mut header = Vec::with_capacity(512);
+        stream
+            .by_ref()
+            .take(512)
+            .read_to_end(&mut header)
+            .map_err(|_| error("IO error reading archive"))?;
+        let format = Self::detect_header(&header)?;
+        let mut stream = Cursor::new(header).chain(stream);
+        if format == ArchiveFormat::Zip {
+            // The index of a zip file is at its end, so it can only be read from a file
+            let mut spooled =
+                tempfile::tempfile().map_err(|_| error("IO error creating temporary file"))?;
+            io::copy(&mut stream, &mut spooled).map_err(|_| error("IO error reading archive"))?;
+            spooled
+                .seek(SeekFrom::Start(0))
+                .map_err(|_| error("IO error reading archive"))?;
+            return Ok(
+                Self::to_template_file(config, &spooled, strip_top_level)?.unwrap_or(spooled)
+            );
+        }
+        let limits = ArchiveLimits::from_config(config);
+        return Self::write_template(format, |writer| {
+            Self::convert_tar(format, stream, writer, strip_top_level, &limits)
+        });
+    }
+
+    /// Write a template file into a temporary file with [`write`]. Returns it rewound to the start
+    fn write_template<F: FnOnce(&mut ZipWriter<File>) -> Result<()>>(
+        format: ArchiveFormat,
+        write: F,
+    ) -> Result<File> {
+        let output = tempfile::tempfile().map_err(|_| error("IO error creating temporary file"))?;
+        let mut writer = ZipWriter::new(output);
+        write(&mut writer)?;
+        let mut output = writer
+            .finish()
+            .map_err(|_| error("IO error writing converted archive"))?;
+        output
+            .seek(SeekFrom::Start(0))
+            .map_err(|_| error("IO error writing converted archive"))?;
+        eprintln!("Converted {} archive into a template", format.name());
+        return Ok(output);
+    }
+
+    /// Copy the entries of a zip file without their shared top level directory
+    fn strip_zip(file: &File, writer: &mut ZipWriter<File>, limits: &ArchiveLimits) -> Result<()> {
+        let mut zip = ZipArchive::new(file).map_err(|_| error("Archive is corrupt"))?;
+        limits.check(&mut zip)?;
+        let mut top_level = None;
+        for i in 0..zip.len() {
+            let entry = zip
+                .by_index_raw(i)
+                .map_err(|_| error("Error reading file from archive"))?;
+            if entry.name() == FOLDR_MANIFEST_FILE {
+                return Err(error(
+                    "The top level directory can't be removed from a template file",
+                ));
+            }
+            let Some(path) = entry.enclosed_name() else {
+                return Err(error(
+                    "Archive contains files trying to escape its path. Archive might be harmful",
+                ));
+            };
+            let Some(path) = Self::strip(&path, &mut top_level)? else {
+                continue;
+            };
+            let mut name = path.to_string_lossy().replace('\\', "/");
+            if entry.is_dir() {
+                name.push('/');
+            }
+            writer
+                .raw_copy_file_rename(entry, name)
+                .map_err(|_| error("IO error writing converted archive"))?;
+        }
+        return Ok(());
+    }
+
+    /// Copy the entries of a tar archive, decompressing it first for tar.gz and tar.zst
+    fn convert_tar<R: Read>(
+        format: ArchiveFormat,
+        stream: R,
+        writer: &mut ZipWriter<File>,
+        strip_top_level: bool,
+        limits: &ArchiveLimits,
+    ) -> Result<()> {
+        return match format {
+            ArchiveFormat::TarGz => Self::copy_tar(
+                GzDecoder::new(BufReader::new(stream)),
+                writer,
+                strip_top_level,
+                limits,
+            ),
+            ArchiveFormat::TarZst => Self::copy_tar(
+                zstd::Decoder::new(stream).map_err(|_| error("Archive is corrupt"))?,
+                writer,
+                strip_top_level,
+                limits,
+            ),
+            _ => Self::copy_tar(stream, writer, strip_top_level, limits),
+        };
+    }
+
+    fn copy_tar<R: Read>(
+        stream: R,
+        writer: &mut ZipWriter<File>,
+        strip_top_level: bool,
+        limits: &ArchiveLimits,
+    ) -> Result<()> {
+        let mut archive = tar::Archive::new(stream);
+        let mut top_level = None;
+        let mut total: u64 = 0;
+        let mut count: usize = 0;
+        let entries = archive.entries().map_err(|_| error("Archive is corrupt"))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| error(&format!("Archive is corrupt: {}", e)))?;
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_pax_global_extensions()
+                || entry_type.is_pax_local_extensions()
+                || entry_type.is_gnu_longname()
+                || entry_type.is_gnu_longlink()
+            {
+                continue;
+            }
+            let path = entry
+                .path()
+                .map_err(|_| error("Archive contains an invalid path"))?
+                .into_owned();
+            if path
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(error(
+                    "Archive contains files trying to escape its path. Archive might be harmful",
+                ));
+            }
+            let path: PathBuf = path
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .collect();
+            if path.as_os_str().is_empty() {
+                continue;
+            }
+            let path = if strip_top_level {
+                match Self::strip(&path, &mut top_level)? {
+                    Some(path) => path,
+                    None => continue,
+                }
+            } else {
+                path
+            };
+
+            count += 1;
+            total = total.saturating_add(entry.size());
+            if limits.max_entries > 0 && count > limits.max_entries {
+                return Err(error(&format!(
+                    "Archive contains more than the limit of {} entries",
+                    limits.max_entries
+                )));
+            }
+            if limits.max_size > 0 && total > limits.max_size {
+                return Err(error(&format!(
+                    "Archive unpacks to more than the limit of {}",
+                    bytesize::ByteSize::b(limits.max_size)
+                )));
+            }
+
+            let mut options =
+                SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+            if let Ok(mode) = entry.header().mode() {
+                options = options.unix_permissions(mode);
+            }
+            if let Ok(mtime) = entry.header().mtime()
+                && let Some(mtime) = SystemTime::UNIX_EPOCH
+                    .checked_add(Duration::from_secs(mtime))
+                    .and_then(ZipUtil::to_zip_time)
+            {
+                options = options.last_modified_time(mtime);
+            }
+            let write_error = |_| error("IO error writing converted archive");
+            match entry_type {
+                EntryType::Directory => writer
+                    .add_directory_from_path(&path, options)
+                    .map_err(write_error)?,
+                EntryType::Regular | EntryType::Continuous => {
+                    writer
+                        .start_file_from_path(&path, options)
+                        .map_err(write_error)?;
+                    io::copy(&mut entry, writer).map_err(|e| {
+                        error(&format!(
+                            "IO error reading {} from archive: {}",
+                            path.to_string_lossy(),
+                            e
+                        ))
+                    })?;
+                }
+                EntryType::Symlink => {
+                    let Some(target) = entry.link_name().ok().flatten() else {
+                        return Err(error("Archive contains a symlink without a target"));
+                    };
+                    writer
+                        .add_symlink(
+                            ZipUtil::entry_name(&path),
+                            target.to_string_lossy(),
+                            options,
+                        )
+                        .map_err(write_error)?;
+                }
+                _ => eprintln!(
+                    "Skipping {}, which is not a file, directory or symlink",
+                    path.to_string_lossy()
+                ),
+            }
+        }
+        return Ok(());
+    }
+
+    /// Remove the top level directory from [`path`]. The first path seen sets the directory in
+    /// [`top_level`], and paths outside of it are an error. Returns None for the directory itself
+    fn strip(path: &Path, top_level: &mut Option<PathBuf>) -> Result<Option<PathBuf>> {
+        let mut components = path.components();
+        let Some(first) = components.next() else {
+            return Ok(None);
+        };
+        let first = PathBuf::from(first.as_os_str());
+        if top_level.get_or_insert_with(|| first.clone()) != &first {
+            return Err(error(
+                "Archive does not have a single top level directory to remove",
+            ));
+        }
+        let rest = components.as_path();
+        if rest.as_os_str().is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(rest.to_path_buf()));
+    }
+}
+
+/// An archive the files of a spawned template are written into, instead of a directory
+pub struct OutputArchive {
+    /// None when the archive is written to stdout
+    path: Option<PathBuf>,
+    format: ArchiveFormat,
+}
+
+impl OutputArchive {
+    /// Pick the format of the archive from the extension of [`path`]. `-` writes a tar stream to
+    /// stdout
+    pub fn new(path: &Path) -> Result<Self> {
+        if path.as_os_str() == "-" {
+            return Ok(Self {
+                path: None,
+                format: ArchiveFormat::Tar,
+            });
+        }
+        let name = path.to_string_lossy().to_lowercase();
+        let format = if name.ends_with(".zip") {
+            ArchiveFormat::Zip
+        } else if name.ends_with(".tar") {
+            ArchiveFormat::Tar
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            ArchiveFormat::TarGz
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            ArchiveFormat::TarZst
+        } else {
+            return Err(error(
+                "Output archive must end in .zip, .tar, .tar.gz or .tar.zst, or be - for a tar stream on stdout",
+            ));
+        };
+        return Ok(Self {
+            path: Some(path.to_path_buf()),
+            format,
+        });
+    }
+
+    pub fn is_stdout(&self) -> bool {
+        return self.path.is_none();
+    }
+
+    /// Where the archive is written, for messages
+    pub fn describe(&self) -> String {
+        return match &self.path {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => "stdout".into(),
+        };
+    }
+
+    /// Write the entries of a template file into the archive, except those in [`hide_from_output`]
+    pub fn write<R: Read + Seek>(
+        &self,
+        zip: &mut ZipArchive<R>,
+        hide_from_output: &[PathBuf],
+    ) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Self::write_tar(zip, hide_from_output, io::stdout().lock());
+        };
+        let file = File::create(path).map_err(|_| {
+            error(&format!(
+                "IO error creating output archive {}",
+                path.to_string_lossy()
+            ))
+        })?;
+        let write_error = |_| error("IO error writing output archive");
+        return match self.format {
+            ArchiveFormat::Zip => Self::write_zip(zip, hide_from_output, file),
+            ArchiveFormat::Tar => Self::write_tar(zip, hide_from_output, file),
+            ArchiveFormat::TarGz => {
+                let mut encoder = GzEncoder::new(file, flate2::Compression::default());
+                Self::write_tar(zip, hide_from_output, &mut encoder)?;
+                encoder.finish().map(|_| ()).map_err(write_error)
+            }
+            ArchiveFormat::TarZst => {
+                let mut encoder = zstd::Encoder::new(file, 0).map_err(write_error)?;
+                Self::write_tar(zip, hide_from_output, &mut encoder)?;
+                encoder.finish().map(|_| ()).map_err(write_error)
+            }
+        };
+    }
+
+    /// Copy the entries of a template file as they are, without recompressing them
+    fn write_zip<R: Read + Seek>(
+        zip: &mut ZipArchive<R>,
+        hide_from_output: &[PathBuf],
+        file: File,
+    ) -> Result<()> {
+        let mut writer = ZipWriter::new(file);
+        for i in 0..zip.len() {
+            let entry = zip
+                .by_index_raw(i)
+                .map_err(|_| error("Error reading file from template"))?;
+            if Self::output_path(entry.enclosed_name(), hide_from_output)?.is_none() {
+                continue;
+            }
+            writer
+                .raw_copy_file(entry)
+                .map_err(|_| error("IO error writing output archive"))?;
+        }
+        writer
+            .finish()
+            .map_err(|_| error("IO error writing output archive"))?;
+        return Ok(());
+    }
+
+    fn write_tar<R: Read + Seek, W: Write>(
+        zip: &mut ZipArchive<R>,
+        hide_from_output: &[PathBuf],
+        output: W,
+    ) -> Result<()> {
+        let mut builder = tar::Builder::new(output);
+        let write_error = |e: io::Error| error(&format!("IO error writing output archive: {}", e));
+        for i in 0..zip.len() {
+            let mut file = zip
+                .by_index(i)
+                .map_err(|_| error("Error reading file from template"))?;
+            let Some(path) = Self::output_path(file.enclosed_name(), hide_from_output)? else {
+                continue;
+            };
+            let mut header = Header::new_gnu();
+            let mtime = file
+                .last_modified()
+                .and_then(ZipUtil::to_system_time)
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_secs());
+            header.set_mtime(mtime);
+            if file.is_dir() {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(file.unix_mode().unwrap_or(0o755));
+                header.set_size(0);
+                builder
+                    .append_data(&mut header, &path, io::empty())
+                    .map_err(write_error)?;
+                continue;
+            }
+            let size = file.size();
+            let is_symlink = file.is_symlink();
+            let mode = file.unix_mode();
+            let mut reader = LimitedReader::new(&mut file, size);
+            if is_symlink {
+                let mut target = String::new();
+                reader
+                    .read_to_string(&mut target)
+                    .map_err(|e| error(&format!("Error reading symlink from template: {}", e)))?;
+                header.set_entry_type(EntryType::Symlink);
+                header.set_mode(mode.unwrap_or(0o777));
+                header.set_size(0);
+                builder
+                    .append_link(&mut header, &path, target)
+                    .map_err(write_error)?;
+                continue;
+            }
+            header.set_entry_type(EntryType::Regular);
+            header.set_mode(mode.unwrap_or(0o644));
+            header.set_size(size);
+            builder
+                .append_data(&mut header, &path, reader)
+                .map_err(write_error)?;
+        }
+        builder
+            .into_inner()
+            .map_err(write_error)?
+            .flush()
+            .map_err(write_error)?;
+        return Ok(());
+    }
+
+    /// Get the path an entry is written at. Returns None for the root and for hidden entries
+    fn output_path(path: Option<PathBuf>, hide_from_output: &[PathBuf]) -> Result<Option<PathBuf>> {
+        let Some(path) = path else {
+            return Err(error(
+                "Template file contains files trying to escape its path. Template might be harmful",
+            ));
+        };
+        if path.as_os_str().is_empty() || hide_from_output.contains(&path) {
+            return Ok(None);
+        }
+        return Ok(Some(path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_template_size: u64, max_template_entries: usize) -> Config {
+        return Config {
+            max_template_size,
+            max_template_entries,
+            ..Config::defaults()
+        };
+    }
+
+    fn append_file(builder: &mut tar::Builder<Vec<u8>>, path: &str, size: usize) {
+        let mut header = Header::new_gnu();
+        // Written as raw bytes, set_path refuses paths with ..
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.set_size(size as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append(&header, &vec![0u8; size][..]).unwrap();
+    }
+
+    fn tar(files: &[(&str, usize)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, size) in files {
+            append_file(&mut builder, path, *size);
+        }
+        return builder.into_inner().unwrap();
+    }
+
+    fn tar_gz(files: &[(&str, usize)]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&tar(files)).unwrap();
+        return encoder.finish().unwrap();
+    }
+
+    fn zip(files: &[(&str, usize)]) -> File {
+        let mut writer = ZipWriter::new(tempfile::tempfile().unwrap());
+        for (path, size) in files {
+            writer
+                .start_file(*path, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(&vec![0u8; *size]).unwrap();
+        }
+        let mut file = writer.finish().unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        return file;
+    }
+
+    #[test]
+    fn tar_archives_over_the_size_limit_are_rejected() {
+        // Compresses to almost nothing, the limit has to hold for the unpacked size
+        let archive = tar_gz(&[("top/a", 600), ("top/b", 600)]);
+        assert!(ForeignArchive::from_stream(&config(1000, 0), &archive[..], true).is_err());
+        assert!(ForeignArchive::from_stream(&config(2000, 0), &archive[..], true).is_ok());
+    }
+
+    #[test]
+    fn tar_archives_over_the_entry_limit_are_rejected() {
+        let archive = tar(&[("a", 1), ("b", 1), ("c", 1)]);
+        assert!(ForeignArchive::from_stream(&config(0, 2), &archive[..], false).is_err());
+        assert!(ForeignArchive::from_stream(&config(0, 3), &archive[..], false).is_ok());
+    }
+
+    #[test]
+    fn zip_archives_over_the_limits_are_rejected() {
+        let files = [("top/a", 600), ("top/b", 600), ("top/c", 600)];
+        for (config, accepted) in [
+            (config(1000, 0), false),
+            (config(0, 2), false),
+            (config(2000, 3), true),
+        ] {
+            let archive = zip(&files);
+            let result = ForeignArchive::to_template_file(&config, &archive, true);
+            assert_eq!(result.is_ok(), accepted);
+        }
+    }
+
+    #[test]
+    fn zip_archives_over_the_compression_ratio_are_rejected() {
+        let archive = zip(&[("top/a", 2 * 1024 * 1024)]);
+        let config = Config {
+            max_compression_ratio: 10,
+            ..config(0, 0)
+        };
+        assert!(ForeignArchive::to_template_file(&config, &archive, true).is_err());
+    }
+
+    #[test]
+    fn tar_entries_escaping_the_archive_are_rejected() {
+        for path in ["../evil", "top/../../evil", "/etc/evil"] {
+            let archive = tar(&[("top/a", 1), (path, 1)]);
+            assert!(
+                ForeignArchive::from_stream(&config(0, 0), &archive[..], false).is_err(),
+                "{} was accepted",
+                path
+            );
+        }
+    }
+}
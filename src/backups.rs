@@ -0,0 +1,398 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    commands::command::{Result, error},
+    config::{Config, ExpandablePathBuf},
+    globals::{
+        FOLDR_BACKUP_INFO_FILE, FOLDR_BLOB_DIR, FOLDR_CONFIG_DIR, FOLDR_CONFIG_FILE,
+        FOLDR_KEYS_DIR, FOLDR_REGISTRIES_FILE, FOLDR_SIGNATURE_EXTENSION, FOLDR_SOURCE_EXTENSION,
+        FOLDR_TEMPLATE_EXTENSION, FOLDR_TREE_EXTENSION, FOLDR_TRUSTED_KEYS_FILE,
+    },
+    zip::{LimitedReader, ZipUtil},
+};
+
+/// Version of the backup layout. Backups with a newer version can't be restored
+pub const BACKUP_FORMAT: u32 = 1;
+
+/// Name of the template directory inside of a backup
+const BACKUP_TEMPLATE_DIR: &str = "templates";
+
+/// A backup of the foldr state written by foldr backup: the template store, the config, registries
+/// and trusted keys, and optionally the signing keys. The download cache is left out, since it can be
+/// downloaded again
+pub struct Backup;
+
+/// Describes a backup. Stored in it as backup.json
+#[derive(Serialize, Deserialize)]
+pub struct BackupInfo {
+    pub format: u32,
+    /// Version of foldr that wrote the backup
+    pub foldr_version: String,
+    /// Unix time the backup was made at
+    pub created: u64,
+    /// Whether the private signing keys are part of the backup
+    #[serde(default)]
+    pub keys: bool,
+    /// Whether the credentials in the config are part of the backup
+    #[serde(default)]
+    pub credentials: bool,
+}
+
+impl Backup {
+    /// Write a backup to [`output`]. Returns the size of the backup
+    pub fn write(
+        config: &Config,
+        output: &Path,
+        include_keys: bool,
+        include_credentials: bool,
+    ) -> Result<u64> {
+        let file = File::create(output).map_err(|_| error("IO error creating backup file"))?;
+        let mut writer = ZipWriter::new(file);
+        let write_error = |_| error("IO error writing backup file");
+        let info = BackupInfo {
+            format: BACKUP_FORMAT,
+            foldr_version: env!("CARGO_PKG_VERSION").into(),
+            created: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            keys: include_keys,
+            credentials: include_credentials,
+        };
+        writer
+            .start_file(FOLDR_BACKUP_INFO_FILE, SimpleFileOptions::default())
+            .map_err(write_error)?;
+        serde_json::to_writer_pretty(&mut writer, &info)
+            .map_err(|_| error("IO error writing backup file"))?;
+        // The config in use, which might have been loaded from a custom path. Its credentials are left
+        // out unless asked for, since they would end up in the backup in plain text
+        let mut backed_up_config =
+            serde_json::to_value(config).map_err(|_| error("Serializer error"))?;
+        if !include_credentials && let Some(backed_up_config) = backed_up_config.as_object_mut() {
+            backed_up_config.remove("credentials");
+        }
+        writer
+            .start_file(Self::name(FOLDR_CONFIG_FILE), SimpleFileOptions::default())
+            .map_err(write_error)?;
+        serde_json::to_writer_pretty(&mut writer, &backed_up_config)
+            .map_err(|_| error("IO error writing backup file"))?;
+
+        for file in [FOLDR_TRUSTED_KEYS_FILE, FOLDR_REGISTRIES_FILE] {
+            let path = PathBuf::from(file).expand();
+            if path.exists() {
+                Self::add_file(&mut writer, &path, &Self::name(file))?;
+            }
+        }
+        if include_keys {
+            Self::add_dir(
+                &mut writer,
+                &PathBuf::from(FOLDR_KEYS_DIR).expand(),
+                &Self::name(FOLDR_KEYS_DIR),
+            )?;
+        }
+        Self::add_dir(
+            &mut writer,
+            &config.template_dir.expand(),
+            BACKUP_TEMPLATE_DIR,
+        )?;
+        let file = writer.finish().map_err(write_error)?;
+        return file
+            .metadata()
+            .map(|m| m.len())
+            .map_err(|_| error("Error querying backup file size"));
+    }
+
+    /// Restore a backup. Templates go into the template directory of the current config, which the
+    /// restored config keeps pointing at, and the config is written to the config file in use.
+    /// Refuses to replace a template store that already holds templates, or existing keys, trusted keys
+    /// and registries unless [`force`] is set, and a template store holding files foldr didn't create at all
+    pub fn restore(config: &Config, path: &Path, force: bool) -> Result<BackupInfo> {
+        let file = File::open(path)
+            .map_err(|_| error(&format!("Unable to open backup {}", path.to_string_lossy())))?;
+        let mut zip =
+            ZipArchive::new(BufReader::new(file)).map_err(|_| error("Backup file is corrupt"))?;
+        let info: BackupInfo =
+            serde_json::from_str(&Self::read_entry(&mut zip, FOLDR_BACKUP_INFO_FILE)?)
+                .map_err(|_| error("Backup info is corrupt"))?;
+        if info.format > BACKUP_FORMAT {
+            return Err(error(&format!(
+                "Backup was made by foldr {}, which uses a newer backup format. Update foldr to restore it",
+                info.foldr_version
+            )));
+        }
+        let restored_config = Self::restored_config(
+            config,
+            &info,
+            &Self::read_entry(&mut zip, &Self::name(FOLDR_CONFIG_FILE))?,
+        )?;
+        let template_dir = config.template_dir.expand();
+        if template_dir
+            .read_dir()
+            .is_ok_and(|mut d| d.next().is_some())
+        {
+            if !force {
+                return Err(error(
+                    "The template store is not empty. Use --force to replace it with the backup",
+                ));
+            }
+            Self::check_store(&template_dir)?;
+        }
+
+        // Everything is unpacked next to the template directory first, so a corrupt backup
+        // leaves the current state alone
+        let parent = template_dir
+            .parent()
+            .ok_or_else(|| error("Invalid template directory"))?;
+        fs::create_dir_all(parent).map_err(|_| error("IO error creating template directory"))?;
+        let staging = tempfile::Builder::new()
+            .prefix(".foldr-restore")
+            .tempdir_in(parent)
+            .map_err(|_| error("IO error creating temporary directory"))?;
+        Self::extract(&mut zip, staging.path())?;
+        // Only the files foldr backs up are restored, whatever else the backup contains
+        let restored_files = [
+            FOLDR_TRUSTED_KEYS_FILE,
+            FOLDR_REGISTRIES_FILE,
+            FOLDR_KEYS_DIR,
+        ]
+        .map(|file| {
+            (
+                staging.path().join(Self::name(file)),
+                PathBuf::from(file).expand(),
+            )
+        });
+        if !force {
+            for (staged, target) in &restored_files {
+                Self::check_not_replaced(staged, target)?;
+            }
+        }
+
+        // The templates are swapped first. The current store is moved aside, and removed together
+        // with the staging directory once everything is restored
+        if template_dir.exists() {
+            fs::rename(&template_dir, staging.path().join(".previous-templates"))
+                .map_err(|_| error("IO error moving the current template store aside"))?;
+        }
+        let staged_templates = staging.path().join(BACKUP_TEMPLATE_DIR);
+        if staged_templates.exists() {
+            fs::rename(&staged_templates, &template_dir)
+                .map_err(|_| error("IO error moving restored templates into place"))?;
+        } else {
+            fs::create_dir_all(&template_dir)
+                .map_err(|_| error("IO error creating template directory"))?;
+        }
+
+        let config_file = config.file.expand();
+        fs::create_dir_all(config_file.parent().unwrap())
+            .map_err(|_| error("IO error creating foldr directory"))?;
+        fs::write(
+            &config_file,
+            serde_json::to_string_pretty(&restored_config).unwrap(),
+        )
+        .map_err(|_| error("IO error restoring config file"))?;
+        for (staged, target) in &restored_files {
+            if staged.exists() {
+                Self::copy_into(staged, target)?;
+            }
+        }
+        return Ok(info);
+    }
+
+    /// The config from a backup, as it is written back. Keys this version of foldr doesn't know are
+    /// kept. template_dir stays the current one, and so do the credentials when the backup has none
+    fn restored_config(
+        config: &Config,
+        info: &BackupInfo,
+        content: &str,
+    ) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let corrupt = || error("Config in backup is corrupt");
+        let Ok(serde_json::Value::Object(mut restored_config)) = serde_json::from_str(content)
+        else {
+            return Err(corrupt());
+        };
+        serde_json::from_value::<Config>(restored_config.clone().into()).map_err(|_| corrupt())?;
+        restored_config.insert(
+            "template_dir".into(),
+            config.template_dir.to_string_lossy().into_owned().into(),
+        );
+        if !info.credentials {
+            restored_config.insert(
+                "credentials".into(),
+                serde_json::to_value(&config.credentials).map_err(|_| corrupt())?,
+            );
+        }
+        return Ok(restored_config);
+    }
+
+    /// Refuse to replace a template store holding files foldr didn't create, in case template_dir
+    /// points at a directory that is used for something else
+    fn check_store(directory: &Path) -> Result<()> {
+        for entry in WalkDir::new(directory).min_depth(1) {
+            let entry =
+                entry.map_err(|_| error("Something went wrong traversing the template store"))?;
+            let relative_path = entry.path().strip_prefix(directory).unwrap();
+            if relative_path.starts_with(FOLDR_BLOB_DIR) {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy();
+            let template_file = name
+                .strip_suffix(&format!(".{}", FOLDR_SIGNATURE_EXTENSION))
+                .or_else(|| name.strip_suffix(&format!(".{}", FOLDR_SOURCE_EXTENSION)))
+                .unwrap_or(&name);
+            let created_by_foldr = entry.depth() == 1
+                && entry.file_type().is_file()
+                && (template_file.ends_with(&format!(".{}", FOLDR_TEMPLATE_EXTENSION))
+                    || template_file.ends_with(&format!(".{}", FOLDR_TREE_EXTENSION))
+                    || name.starts_with(".foldr-store"));
+            if !created_by_foldr {
+                return Err(error(&format!(
+                    "The template directory contains {}, which foldr didn't create. Refusing to replace it",
+                    entry.path().to_string_lossy()
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    /// Name of a file in the foldr directory inside of a backup
+    fn name(path: &str) -> String {
+        return path
+            .trim_start_matches(FOLDR_CONFIG_DIR)
+            .trim_end_matches('/')
+            .to_string();
+    }
+
+    fn add_file(writer: &mut ZipWriter<File>, path: &Path, name: &str) -> Result<()> {
+        let mut file = File::open(path).map_err(|_| {
+            error(&format!(
+                "IO error reading {} for backup",
+                path.to_string_lossy()
+            ))
+        })?;
+        let mut options = SimpleFileOptions::default();
+        if let Ok(metadata) = file.metadata() {
+            if let Some(mode) = ZipUtil::unix_mode(&metadata) {
+                options = options.unix_permissions(mode);
+            }
+            // Templates are compressed already
+            if metadata.len() > 0 && name.starts_with(BACKUP_TEMPLATE_DIR) {
+                options = options.compression_method(CompressionMethod::Stored);
+            }
+        }
+        writer
+            .start_file(name, options)
+            .map_err(|_| error("IO error writing backup file"))?;
+        io::copy(&mut file, writer).map_err(|_| error("IO error writing backup file"))?;
+        return Ok(());
+    }
+
+    /// Add every file below [`directory`] with its path prefixed by [`name`]. Symlinks are left out,
+    /// foldr doesn't create them in its own directories
+    fn add_dir(writer: &mut ZipWriter<File>, directory: &Path, name: &str) -> Result<()> {
+        if !directory.exists() {
+            return Ok(());
+        }
+        for entry in WalkDir::new(directory).sort_by_file_name() {
+            let entry =
+                entry.map_err(|_| error("Something went wrong traversing the foldr directory"))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative_path = entry.path().strip_prefix(directory).unwrap();
+            let entry_name = Path::new(name)
+                .join(relative_path)
+                .to_string_lossy()
+                .replace('\\', "/");
+            Self::add_file(writer, entry.path(), &entry_name)?;
+        }
+        return Ok(());
+    }
+
+    fn read_entry(zip: &mut ZipArchive<BufReader<File>>, name: &str) -> Result<String> {
+        let mut file = zip
+            .by_name(name)
+            .map_err(|_| error(&format!("Backup does not contain {}", name)))?;
+        let size = file.size();
+        let mut content = String::new();
+        LimitedReader::new(&mut file, size)
+            .read_to_string(&mut content)
+            .map_err(|_| error(&format!("Error reading {} from backup", name)))?;
+        return Ok(content);
+    }
+
+    fn extract(zip: &mut ZipArchive<BufReader<File>>, directory: &Path) -> Result<()> {
+        for i in 0..zip.len() {
+            let mut file = zip
+                .by_index(i)
+                .map_err(|_| error("Error reading file from backup"))?;
+            let Some(relative_path) = file.enclosed_name() else {
+                return Err(error(
+                    "Backup contains files trying to escape its path. Backup might be harmful",
+                ));
+            };
+            if file.is_dir() || file.is_symlink() {
+                continue;
+            }
+            let out_path = directory.join(relative_path);
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|_| error("IO error creating directory from backup"))?;
+            }
+            let mode = file.unix_mode();
+            let size = file.size();
+            ZipUtil::write_file(
+                &mut LimitedReader::new(&mut file, size),
+                &out_path,
+                mode,
+                None,
+            )?;
+        }
+        return Ok(());
+    }
+
+    /// Copy a restored file or directory from the staging directory to [`target`], replacing
+    /// files that exist already
+    /// Refuse to restore [`source`] when that replaces an existing file in [`target`]
+    fn check_not_replaced(source: &Path, target: &Path) -> Result<()> {
+        if source.is_dir() {
+            for entry in fs::read_dir(source)
+                .map_err(|_| error("IO error reading restored files"))?
+                .flatten()
+            {
+                Self::check_not_replaced(&entry.path(), &target.join(entry.file_name()))?;
+            }
+            return Ok(());
+        }
+        if source.exists() && target.exists() {
+            return Err(error(&format!(
+                "{} already exists. Use --force to replace it with the one from the backup",
+                target.to_string_lossy()
+            )));
+        }
+        return Ok(());
+    }
+
+    fn copy_into(source: &Path, target: &Path) -> Result<()> {
+        if source.is_dir() {
+            fs::create_dir_all(target).map_err(|_| error("IO error restoring directory"))?;
+            for entry in fs::read_dir(source)
+                .map_err(|_| error("IO error reading restored files"))?
+                .flatten()
+            {
+                Self::copy_into(&entry.path(), &target.join(entry.file_name()))?;
+            }
+            return Ok(());
+        }
+        fs::copy(source, target)
+            .map_err(|_| error(&format!("IO error restoring {}", target.to_string_lossy())))?;
+        return Ok(());
+    }
+}
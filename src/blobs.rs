@@ -0,0 +1,533 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytesize::ByteSize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::{
+    commands::command::{Result, error},
+    config::{Config, ExpandablePathBuf},
+    globals,
+    templates::{Template, TemplateInfo},
+    zip::{ArchiveOptions, ZipUtil},
+};
+
+/// Storage backend that keeps file contents once in a content addressed blob directory.
+/// Every template iteration is a small tree file mapping paths to the blobs holding their contents
+pub struct BlobStore;
+
+/// Which storage backend new templates are written to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Every iteration is a self contained `.foldr` archive
+    #[default]
+    Archive,
+    /// File contents are deduplicated across iterations in the blob store
+    Blobs,
+}
+
+/// The kind of an entry inside of a template tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// A single file, directory or symlink of a template tree
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TreeEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    /// Sha256 of the file contents, which is also the name of the blob holding them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Target of a symlink
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the unix epoch. Only recorded for templates saved with --preserve-mtime
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<u64>,
+    #[serde(default)]
+    pub size: u64,
+}
+
+/// A single template iteration stored in the blob store
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TemplateTree {
+    pub info: TemplateInfo,
+    pub entries: Vec<TreeEntry>,
+}
+
+/// Writer that hashes everything written through it
+pub struct HashingWriter<W: Write> {
+    pub inner: W,
+    pub hasher: Sha256,
+    pub written: u64,
+}
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        return Self {
+            inner,
+            hasher: Sha256::new(),
+            written: 0,
+        };
+    }
+    pub fn hash(self) -> String {
+        return format!("{:x}", self.hasher.finalize());
+    }
+}
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.written += written as u64;
+        return Ok(written);
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        return self.inner.flush();
+    }
+}
+
+impl TemplateTree {
+    /// Total size of all files in the tree
+    pub fn size(&self) -> u64 {
+        return self.entries.iter().map(|e| e.size).sum();
+    }
+}
+
+/// Age an unreferenced blob needs before gc removes it. A save running at the same time writes its
+/// blobs before the tree file referencing them, so newer blobs might be about to be used
+const BLOB_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+impl BlobStore {
+    /// Get the blob directory belonging to the template directory a template file is stored in
+    pub fn blob_dir(template_file: &Path) -> PathBuf {
+        return template_file
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(globals::FOLDR_BLOB_DIR);
+    }
+    fn blob_path(blob_dir: &Path, hash: &str) -> Result<PathBuf> {
+        // Hashes are read from tree files, so make sure they can't point outside the blob store
+        if hash.len() != 64 || !hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+            return Err(error("Template tree contains an invalid blob hash"));
+        }
+        return Ok(blob_dir.join(&hash[..2]).join(hash));
+    }
+
+    /// Move the contents of a template file into the blob store, replacing the file by a tree file
+    pub fn ingest(template: &Template) -> Result<Template> {
+        let file = File::open(&template.filename)
+            .map_err(|_| error("IO error while opening template file"))?;
+        let mut zip = ZipArchive::new(BufReader::new(file))
+            .map_err(|_| error("Error unzipping template file"))?;
+        let info = ZipUtil::read_manifest(&mut zip)?;
+        let blob_dir = Self::blob_dir(&template.filename);
+        let mut entries = vec![];
+
+        for i in 0..zip.len() {
+            let mut file = zip
+                .by_index(i)
+                .map_err(|_| error("Error reading file from template"))?;
+            let Some(path) = file.enclosed_name() else {
+                return Err(error(
+                    "Template file contains files trying to escape its path. Template might be harmful",
+                ));
+            };
+            if path.as_os_str().is_empty() || path == Path::new(globals::FOLDR_MANIFEST_FILE) {
+                continue;
+            }
+            let mtime = if info.preserve_mtime {
+                file.last_modified()
+                    .and_then(ZipUtil::to_system_time)
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs())
+            } else {
+                None
+            };
+            let mut entry = TreeEntry {
+                path,
+                kind: EntryKind::File,
+                hash: None,
+                target: None,
+                mode: file.unix_mode(),
+                mtime,
+                size: 0,
+            };
+            if file.is_dir() {
+                entry.kind = EntryKind::Directory;
+            } else if file.is_symlink() {
+                let mut target = String::new();
+                file.read_to_string(&mut target)
+                    .map_err(|_| error("Error reading symlink from template"))?;
+                entry.kind = EntryKind::Symlink;
+                entry.target = Some(target.into());
+            } else {
+                let (hash, size) = Self::write_blob(&blob_dir, &mut file)?;
+                entry.hash = Some(hash);
+                entry.size = size;
+            }
+            entries.push(entry);
+        }
+
+        let tree = TemplateTree { info, entries };
+        let tree_path = template
+            .filename
+            .with_extension(globals::FOLDR_TREE_EXTENSION);
+        // Replaces an existing tree file in one step, so it is never left half written
+        let mut tree_file = tempfile::Builder::new()
+            .prefix(".foldr-store")
+            .tempfile_in(tree_path.parent().unwrap())
+            .map_err(|_| error("IO error writing template tree file"))?;
+        tree_file
+            .write_all(serde_json::to_string_pretty(&tree).unwrap().as_bytes())
+            .map_err(|_| error("IO error writing template tree file"))?;
+        tree_file
+            .persist(&tree_path)
+            .map_err(|_| error("IO error writing template tree file"))?;
+        fs::remove_file(&template.filename)
+            .map_err(|_| error("IO error removing intermediate template file"))?;
+
+        return Ok(Template {
+            info: tree.info.clone(),
+            filename: tree_path,
+            filesize: ByteSize::b(tree.size()),
+        });
+    }
+
+    /// Store the contents of [`reader`] as a blob, returning its hash and size
+    fn write_blob<R: Read>(blob_dir: &Path, reader: &mut R) -> Result<(String, u64)> {
+        fs::create_dir_all(blob_dir).map_err(|_| error("IO error creating blob directory"))?;
+        let incoming_path = blob_dir.join(format!(".incoming-{}", std::process::id()));
+        let incoming =
+            File::create(&incoming_path).map_err(|_| error("IO error creating blob file"))?;
+        let mut writer = HashingWriter::new(incoming);
+        io::copy(reader, &mut writer).map_err(|_| error("IO error writing blob file"))?;
+        let size = writer.written;
+        let hash = writer.hash();
+
+        let blob_path = Self::blob_path(blob_dir, &hash)?;
+        if blob_path.exists() {
+            fs::remove_file(&incoming_path).map_err(|_| error("IO error removing blob file"))?;
+            // Used again, so gc has to keep it for the grace period like a new blob
+            File::options()
+                .write(true)
+                .open(&blob_path)
+                .and_then(|blob| blob.set_modified(SystemTime::now()))
+                .map_err(|_| error("IO error updating blob file"))?;
+        } else {
+            fs::create_dir_all(blob_path.parent().unwrap())
+                .map_err(|_| error("IO error creating blob directory"))?;
+            fs::rename(&incoming_path, &blob_path)
+                .map_err(|_| error("IO error moving blob file into place"))?;
+        }
+        return Ok((hash, size));
+    }
+
+    pub fn read_tree(tree_path: &PathBuf) -> Result<TemplateTree> {
+        let content = fs::read_to_string(tree_path)
+            .map_err(|_| error("IO error while opening template tree file"))?;
+        let tree: TemplateTree =
+            serde_json::from_str(&content).map_err(|_| error("Template tree file corrupt"))?;
+        let escapes = tree.entries.iter().any(|e| {
+            e.path
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        });
+        if escapes {
+            return Err(error(
+                "Template tree contains files trying to escape its path. Template might be harmful",
+            ));
+        }
+        return Ok(tree);
+    }
+
+    pub fn read_template(tree_path: &PathBuf) -> Result<Template> {
+        let tree = Self::read_tree(tree_path)?;
+        return Ok(Template {
+            filesize: ByteSize::b(tree.size()),
+            info: tree.info,
+            filename: tree_path.clone(),
+        });
+    }
+
+    /// Get the paths of all entries in a template tree
+    pub fn get_files(template: &Template) -> Result<Vec<PathBuf>> {
+        let tree = Self::read_tree(&template.filename)?;
+        return Ok(tree.entries.into_iter().map(|e| e.path).collect());
+    }
+
+    /// Hash the stored contents of every file and symlink in a template tree
+    pub fn hash_entries(template: &Template) -> Result<BTreeMap<String, String>> {
+        let tree = Self::read_tree(&template.filename)?;
+        let mut files = BTreeMap::new();
+        for entry in &tree.entries {
+            let hash = match entry.kind {
+                EntryKind::Directory => continue,
+                EntryKind::Symlink => format!(
+                    "{:x}",
+                    Sha256::digest(
+                        entry
+                            .target
+                            .clone()
+                            .unwrap_or_default()
+                            .to_string_lossy()
+                            .as_bytes()
+                    )
+                ),
+                EntryKind::File => {
+                    let mut hasher = Sha256::new();
+                    io::copy(&mut Self::open_blob(template, entry)?, &mut hasher)
+                        .map_err(|_| error("IO error while hashing blob"))?;
+                    format!("{:x}", hasher.finalize())
+                }
+            };
+            files.insert(entry.path.to_string_lossy().into_owned(), hash);
+        }
+        return Ok(files);
+    }
+
+    /// Open the blob holding the contents of a file entry
+    pub fn open_blob(template: &Template, entry: &TreeEntry) -> Result<File> {
+        let Some(hash) = &entry.hash else {
+            return Err(error(&format!(
+                "Template tree entry {} has no blob",
+                entry.path.to_string_lossy()
+            )));
+        };
+        let blob_path = Self::blob_path(&Self::blob_dir(&template.filename), hash)?;
+        return File::open(&blob_path).map_err(|_| {
+            error(&format!(
+                "Blob {} for {} is missing from the blob store",
+                hash,
+                entry.path.to_string_lossy()
+            ))
+        });
+    }
+
+    /// Spawn a blob backed template at [`spawn_path`]
+    pub fn spawn(template: &Template, spawn_path: &Path) -> Result<()> {
+        let tree = Self::read_tree(&template.filename)?;
+        let mut symlinks: Vec<(PathBuf, PathBuf)> = vec![];
+        let mut directories: Vec<(PathBuf, Option<u32>, Option<SystemTime>)> = vec![];
+
+        for entry in &tree.entries {
+            let out_path = spawn_path.join(&entry.path);
+            let mtime = entry.mtime.map(|s| UNIX_EPOCH + Duration::from_secs(s));
+            if entry.kind == EntryKind::Directory {
+                fs::create_dir_all(&out_path)
+                    .map_err(|_| error("IO error creating directory from template"))?;
+                directories.push((out_path, entry.mode, mtime));
+                continue;
+            }
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|_| error("IO error creating directory from template"))?;
+            }
+            if entry.kind == EntryKind::Symlink {
+                symlinks.push((out_path, entry.target.clone().unwrap_or_default()));
+                continue;
+            }
+            let mut blob = Self::open_blob(template, entry)?;
+            ZipUtil::write_file(&mut blob, &out_path, entry.mode, mtime)?;
+        }
+
+        return ZipUtil::finish_extract(spawn_path, symlinks, directories);
+    }
+
+    /// Write a blob backed template as a single self contained template file, for sharing
+    pub fn write_archive<W: Write + Seek>(
+        template: &Template,
+        options: &ArchiveOptions,
+        output: W,
+    ) -> Result<()> {
+        let tree = Self::read_tree(&template.filename)?;
+        let mut writer = ZipWriter::new(output);
+        writer
+            .start_file(
+                globals::FOLDR_MANIFEST_FILE,
+                options.file_options(Path::new(globals::FOLDR_MANIFEST_FILE)),
+            )
+            .map_err(|_| error("Error writing template manifest file"))?;
+        writer
+            .write_all(serde_json::to_string_pretty(&tree.info).unwrap().as_bytes())
+            .map_err(|_| error("Error writing template manifest file"))?;
+
+        for entry in &tree.entries {
+            let mut file_options = options.file_options(&entry.path);
+            if let Some(mode) = entry.mode {
+                file_options = file_options.unix_permissions(mode);
+            }
+            if let Some(mtime) = entry
+                .mtime
+                .and_then(|s| ZipUtil::to_zip_time(UNIX_EPOCH + Duration::from_secs(s)))
+            {
+                file_options = file_options.last_modified_time(mtime);
+            }
+            match entry.kind {
+                EntryKind::Directory => {
+                    writer
+                        .add_directory_from_path(&entry.path, file_options)
+                        .map_err(|_| error("Error creating directory in template file"))?
+                }
+                EntryKind::Symlink => writer
+                    .add_symlink(
+                        ZipUtil::entry_name(&entry.path),
+                        entry.target.clone().unwrap_or_default().to_string_lossy(),
+                        file_options,
+                    )
+                    .map_err(|_| error("Error writing symlink in template file"))?,
+                EntryKind::File => {
+                    writer
+                        .start_file_from_path(&entry.path, file_options)
+                        .map_err(|e| {
+                            error(&format!("Error creating file in template file: {}", e))
+                        })?;
+                    let mut blob = Self::open_blob(template, entry)?;
+                    io::copy(&mut blob, &mut writer)
+                        .map_err(|_| error("IO error while writing file to template"))?;
+                }
+            }
+        }
+        writer
+            .finish()
+            .map_err(|_| error("Failure to compress template file on disk"))?;
+        return Ok(());
+    }
+
+    /// Remove all blobs that are not referenced by any stored template tree, and were not written
+    /// within the grace period.
+    /// Returns the amount of removed blobs and the bytes freed
+    pub fn gc(config: &Config, dry_run: bool) -> Result<(usize, u64)> {
+        let template_dir = config.template_dir.expand();
+        let blob_dir = template_dir.join(globals::FOLDR_BLOB_DIR);
+        if !blob_dir.is_dir() {
+            return Ok((0, 0));
+        }
+        let mut referenced = HashSet::new();
+        for template in ZipUtil::get_templates(&template_dir)? {
+            if !template.is_tree() {
+                continue;
+            }
+            for entry in Self::read_tree(&template.filename)?.entries {
+                if let Some(hash) = entry.hash {
+                    referenced.insert(hash);
+                }
+            }
+        }
+
+        let mut removed = 0;
+        let mut freed = 0;
+        for entry in WalkDir::new(&blob_dir).min_depth(1) {
+            let entry = entry.map_err(|_| error("Error traversing blob directory"))?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if referenced.contains(&name) {
+                continue;
+            }
+            // Blobs and .incoming- files of a save running at the same time
+            if entry
+                .metadata()
+                .ok()
+                .and_then(|m| m.modified().ok())
+                .and_then(|modified| modified.elapsed().ok())
+                .is_none_or(|age| age < BLOB_GRACE_PERIOD)
+            {
+                continue;
+            }
+            freed += entry.metadata().map(|m| m.len()).unwrap_or(0);
+            removed += 1;
+            if !dry_run {
+                fs::remove_file(entry.path())
+                    .map_err(|_| error("IO error removing unreferenced blob"))?;
+            }
+        }
+        return Ok((removed, freed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    fn config(template_dir: &Path) -> Config {
+        return Config {
+            template_dir: template_dir.to_path_buf(),
+            ..Config::defaults()
+        };
+    }
+
+    fn write_blob_file(blob_dir: &Path, hash: &str, age: Duration) -> PathBuf {
+        let path = BlobStore::blob_path(blob_dir, hash).unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let file = File::create(&path).unwrap();
+        file.set_modified(SystemTime::now() - age).unwrap();
+        return path;
+    }
+
+    #[test]
+    fn invalid_blob_hashes_are_rejected() {
+        let blob_dir = Path::new("blobs");
+        for hash in [
+            "",
+            "a",
+            "../../../../etc/passwd",
+            &HASH[..63],
+            &HASH.to_uppercase(),
+            &format!("{}/..", &HASH[..61]),
+        ] {
+            assert!(
+                BlobStore::blob_path(blob_dir, hash).is_err(),
+                "{} was accepted",
+                hash
+            );
+        }
+        assert!(BlobStore::blob_path(blob_dir, HASH).is_ok());
+    }
+
+    #[test]
+    fn gc_keeps_recent_unreferenced_blobs() {
+        let template_dir = tempfile::tempdir().unwrap();
+        let blob_dir = template_dir.path().join(globals::FOLDR_BLOB_DIR);
+        let old = write_blob_file(&blob_dir, HASH, BLOB_GRACE_PERIOD * 2);
+        let recent = write_blob_file(&blob_dir, &HASH.replace('9', "8"), Duration::ZERO);
+        let (removed, _) = BlobStore::gc(&config(template_dir.path()), false).unwrap();
+        assert_eq!(removed, 1);
+        assert!(!old.exists());
+        assert!(recent.exists());
+    }
+
+    #[test]
+    fn gc_removes_nothing_when_a_tree_is_unreadable() {
+        let template_dir = tempfile::tempdir().unwrap();
+        let blob_dir = template_dir.path().join(globals::FOLDR_BLOB_DIR);
+        let blob = write_blob_file(&blob_dir, HASH, BLOB_GRACE_PERIOD * 2);
+        // A tree that can't be read might reference any blob
+        fs::write(
+            template_dir
+                .path()
+                .join(format!("{}-1.{}", HASH, globals::FOLDR_TREE_EXTENSION)),
+            "not a tree",
+        )
+        .unwrap();
+        assert!(BlobStore::gc(&config(template_dir.path()), false).is_err());
+        assert!(blob.exists());
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    fs::{self, File},
+    io::{self, Seek, SeekFrom},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use crate::{
+    blobs::HashingWriter,
+    commands::command::{CommandError, Result, error},
+    config::ExpandablePathBuf,
+    credentials::Credentials,
+    globals,
+    signing::TemplateSignature,
+    sources::TemplateSource,
+};
+
+/// Downloaded files, stored by the sha256 of their contents, with an entry per url pointing at the
+/// file last downloaded from it. Lets spawns and fetches work offline, and lets the server skip
+/// sending files that did not change
+pub struct DownloadCache;
+
+/// The file last downloaded from a url
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// The url with any credentials removed, and the validators and sha256 of the download
+    #[serde(flatten)]
+    pub source: TemplateSource,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<TemplateSignature>,
+    pub size: u64,
+}
+
+impl CacheEntry {
+    /// Open the cached file, checking it still has the contents it was stored with
+    pub fn open(&self) -> Result<File> {
+        let corrupt = || {
+            error(&format!(
+                "Cached copy of {} is corrupt. Remove it with foldr cache clear",
+                self.source.url
+            ))
+        };
+        let mut file =
+            File::open(DownloadCache::file_path(&self.source.sha256)?).map_err(|_| corrupt())?;
+        let mut writer = HashingWriter::new(io::sink());
+        io::copy(&mut file, &mut writer).map_err(|_| corrupt())?;
+        if writer.hash() != self.source.sha256 {
+            return Err(corrupt());
+        }
+        file.seek(SeekFrom::Start(0)).map_err(|_| corrupt())?;
+        return Ok(file);
+    }
+}
+
+impl DownloadCache {
+    pub fn dir() -> PathBuf {
+        return PathBuf::from(globals::FOLDR_CACHE_DIR).expand();
+    }
+
+    fn entry_path(url: &str) -> PathBuf {
+        let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+        return Self::dir().join("entries").join(format!("{}.json", key));
+    }
+
+    fn file_path(sha256: &str) -> Result<PathBuf> {
+        // Entries are read from disk, so make sure the hash can't point outside the cache
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(error("Download cache entry is corrupt"));
+        }
+        return Ok(Self::dir().join("files").join(sha256));
+    }
+
+    /// Get the cache entry for [`url`], if it was downloaded before
+    pub fn get(url: &str) -> Result<Option<CacheEntry>> {
+        let path = Self::entry_path(url);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|_| error("IO error reading download cache"))?;
+        return serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|_| error("Download cache entry is corrupt"));
+    }
+
+    /// Store the file downloaded from [`url`], which is left at an unspecified position
+    pub fn store(
+        url: &str,
+        mut file: &File,
+        source: &TemplateSource,
+        signature: Option<&TemplateSignature>,
+    ) -> Result<()> {
+        let file_path = Self::file_path(&source.sha256)?;
+        let files_dir = file_path.parent().unwrap();
+        fs::create_dir_all(files_dir).map_err(|_| error("IO error creating download cache"))?;
+        file.seek(SeekFrom::Start(0))
+            .map_err(|_| error("IO error reading download"))?;
+        if !file_path.exists() {
+            // Copy to a temporary file first, so an interrupted copy never looks like a cached file
+            let mut temp = NamedTempFile::new_in(files_dir)
+                .map_err(|_| error("IO error writing download cache"))?;
+            io::copy(&mut file, &mut temp).map_err(|_| error("IO error writing download cache"))?;
+            temp.persist(&file_path)
+                .map_err(|_| error("IO error writing download cache"))?;
+        }
+        let size = fs::metadata(&file_path)
+            .map_err(|_| error("IO error reading download cache"))?
+            .len();
+
+        let entry = CacheEntry {
+            source: TemplateSource {
+                url: Credentials::redact_url(url),
+                reference: None,
+                ..source.clone()
+            },
+            signature: signature.cloned(),
+            size,
+        };
+        let entry_path = Self::entry_path(url);
+        fs::create_dir_all(entry_path.parent().unwrap())
+            .map_err(|_| error("IO error creating download cache"))?;
+        return fs::write(entry_path, serde_json::to_string_pretty(&entry).unwrap())
+            .map_err(|_| error("IO error writing download cache"));
+    }
+
+    /// Get all cache entries
+    pub fn entries() -> Result<Vec<CacheEntry>> {
+        let dir = Self::dir().join("entries");
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut entries = vec![];
+        for file in fs::read_dir(&dir).map_err(|_| error("IO error reading download cache"))? {
+            let file = file.map_err(|_| error("IO error reading download cache"))?;
+            let content = fs::read_to_string(file.path())
+                .map_err(|_| error("IO error reading download cache"))?;
+            let entry: CacheEntry = serde_json::from_str(&content)
+                .map_err(|_| error("Download cache entry is corrupt"))?;
+            entries.push(entry);
+        }
+        entries.sort_by(|a, b| a.source.url.cmp(&b.source.url));
+        return Ok(entries);
+    }
+
+    /// Remove everything in the cache. Returns the number of bytes freed
+    pub fn clear() -> Result<u64> {
+        let dir = Self::dir();
+        if !dir.exists() {
+            return Ok(0);
+        }
+        let mut freed = 0;
+        for file in walkdir::WalkDir::new(&dir).into_iter().flatten() {
+            if file.file_type().is_file() {
+                freed += file.metadata().map(|m| m.len()).unwrap_or(0);
+            }
+        }
+        fs::remove_dir_all(&dir).map_err(|_| error("IO error clearing download cache"))?;
+        return Ok(freed);
+    }
+
+    /// Error for a url that is needed while offline but was never downloaded
+    pub fn not_cached(url: &str) -> CommandError {
+        return error(&format!(
+            "{} is not in the download cache, and foldr is offline. Fetch it once while online to cache it",
+            Credentials::redact_url(url)
+        ));
+    }
+}
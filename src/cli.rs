@@ -11,9 +11,21 @@ use std::path::PathBuf;
 #[command(version = "1.0")]
 #[command(about = "foldr, the blazing fast templating tool")]
 pub struct Cli {
-    #[arg(short, long, name = "config")]
+    #[arg(
+        short,
+        long = "config",
+        name = "config",
+        help = "Path to a custom config file"
+    )]
     pub config_path: Option<PathBuf>,
 
+    #[arg(
+        long,
+        global = true,
+        help = "Only use the download cache for remote templates, without making network requests"
+    )]
+    pub offline: bool,
+
     #[command(subcommand)]
     pub command: Option<Command>,
 }
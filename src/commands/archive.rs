@@ -0,0 +1,71 @@
+use std::{fs, path::PathBuf};
+
+use clap::Args;
+
+use crate::{
+    config::{Config, ExpandablePathBuf},
+    globals,
+    signing::Signing,
+    templates::Template,
+};
+
+use super::command::{Iteration, Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct ArchiveCommand {
+    #[arg(help = "The template to write as a single template file")]
+    pub template_name: String,
+    #[arg(
+        short,
+        long,
+        help = "The version of the template to write. Defaults to the most recent version"
+    )]
+    pub iteration: Option<Iteration>,
+    #[arg(
+        short,
+        long,
+        help = "The file to write the template to. Defaults to <name>-<version>.foldr in the current directory"
+    )]
+    pub output: Option<PathBuf>,
+}
+
+impl RunCommand for ArchiveCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let template = if let Some(iteration) = self.iteration {
+            Template::get_existing_by_name_and_iteration(&config, &self.template_name, iteration)?
+        } else {
+            Template::get_existing_by_name(&config, &self.template_name)?
+        };
+        let Some(template) = template else {
+            return Err(error("Template or template version not found"));
+        };
+        let output = self.output.clone().unwrap_or_else(|| {
+            PathBuf::from(format!(
+                "{}-{}.foldr",
+                template.info.name.replace("/", "-"),
+                template.info.iteration
+            ))
+        });
+        let output = output.expand();
+        let size = template.write_archive(&config, &output)?;
+        if let Some(signature) = Signing::read_signature(&template)? {
+            // Published next to the template file, where fetch looks for it
+            let mut signature_path = output.clone().into_os_string();
+            signature_path.push(".");
+            signature_path.push(globals::FOLDR_SIGNATURE_EXTENSION);
+            fs::write(
+                &signature_path,
+                serde_json::to_string_pretty(&signature).unwrap(),
+            )
+            .map_err(|_| error("IO error writing template signature"))?;
+        }
+        println!(
+            "Wrote template {} version {} to {}\nSize: {}",
+            template.info.name,
+            template.info.iteration,
+            output.to_string_lossy(),
+            bytesize::ByteSize::b(size)
+        );
+        return Ok(());
+    }
+}
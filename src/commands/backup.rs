@@ -0,0 +1,54 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::{
+    backups::Backup,
+    config::{Config, ExpandablePathBuf},
+};
+
+use super::command::{Result, RunCommand};
+
+#[derive(Args, Debug)]
+pub struct BackupCommand {
+    #[arg(help = "The file to write the backup to, like foldr-backup.zip")]
+    pub output: PathBuf,
+    #[arg(
+        long,
+        help = "Also back up the private keys used by foldr sign. Keep the backup somewhere safe when using this"
+    )]
+    pub include_keys: bool,
+    #[arg(
+        long,
+        help = "Also back up the credentials in the config. They are stored in the backup in plain text"
+    )]
+    pub include_credentials: bool,
+}
+
+impl RunCommand for BackupCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let output = self.output.expand();
+        let size = Backup::write(
+            &config,
+            &output,
+            self.include_keys,
+            self.include_credentials,
+        )?;
+        println!(
+            "Backed up foldr to {}\nSize: {}",
+            output.to_string_lossy(),
+            bytesize::ByteSize::b(size)
+        );
+        if !self.include_keys {
+            println!(
+                "Private signing keys are not part of the backup, use --include-keys to add them"
+            );
+        }
+        if !self.include_credentials && !config.credentials.is_empty() {
+            println!(
+                "Credentials are not part of the backup, use --include-credentials to add them"
+            );
+        }
+        return Ok(());
+    }
+}
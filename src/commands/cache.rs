@@ -0,0 +1,59 @@
+use bytesize::ByteSize;
+use clap::{Args, Subcommand};
+
+use crate::{cache::DownloadCache, config::Config};
+
+use super::command::{Result, RunCommand};
+
+#[derive(Args, Debug)]
+pub struct CacheCommand {
+    #[command(subcommand)]
+    pub action: CacheAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheAction {
+    #[command(
+        visible_alias = "ls",
+        about = "List the downloaded files in the download cache"
+    )]
+    List,
+    #[command(about = "Remove everything from the download cache")]
+    Clear,
+}
+
+impl RunCommand for CacheCommand {
+    fn run(&self, _config: Config) -> Result<()> {
+        match &self.action {
+            CacheAction::List => {
+                let entries = DownloadCache::entries()?;
+                if entries.is_empty() {
+                    println!("The download cache is empty");
+                    return Ok(());
+                }
+                for entry in entries {
+                    println!(
+                        "{}\n  {} sha256 {}{}",
+                        entry.source.url,
+                        ByteSize::b(entry.size),
+                        entry.source.sha256,
+                        if entry.signature.is_some() {
+                            ", signed"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+                println!(
+                    "Cache directory: {}",
+                    DownloadCache::dir().to_string_lossy()
+                );
+            }
+            CacheAction::Clear => {
+                let freed = DownloadCache::clear()?;
+                println!("Cleared the download cache, freeing {}", ByteSize::b(freed));
+            }
+        }
+        return Ok(());
+    }
+}
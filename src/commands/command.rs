@@ -3,9 +3,13 @@ use clap::Subcommand;
 use crate::config::Config;
 
 use super::{
-    config::ConfigCommand, delete::DeleteCommand, fetch::FetchCommand, list::ListCommand,
-    new::NewCommand, purge::PurgeCommand, save::SaveCommand, show::ShowCommand,
-    update::UpdateCommand,
+    archive::ArchiveCommand, backup::BackupCommand, cache::CacheCommand, config::ConfigCommand,
+    delete::DeleteCommand, export::ExportCommand, fetch::FetchCommand, gc::GcCommand,
+    import::ImportCommand, keygen::KeygenCommand, list::ListCommand, new::NewCommand,
+    publish::PublishCommand, purge::PurgeCommand, refresh::RefreshCommand,
+    registry::RegistryCommand, restore::RestoreCommand, save::SaveCommand, search::SearchCommand,
+    serve::ServeCommand, show::ShowCommand, sign::SignCommand, store::StoreCommand,
+    trust::TrustCommand, update::UpdateCommand, verify::VerifyCommand,
 };
 
 /// Stores the kind of command being ran by the terminal. every command is a oneshot command that runs and exits.
@@ -30,6 +34,44 @@ pub enum Command {
     Delete(DeleteCommand),
     #[command(about = "Generate the default configuration")]
     Config(ConfigCommand),
+    #[command(about = "Remove blobs no longer used by any template from the blob store")]
+    Gc(GcCommand),
+    #[command(about = "Write a template as a single template file for sharing")]
+    Archive(ArchiveCommand),
+    #[command(about = "Check stored templates against the hashes recorded when they were saved")]
+    Verify(VerifyCommand),
+    #[command(about = "Generate a key pair for signing templates")]
+    Keygen(KeygenCommand),
+    #[command(about = "Sign a template with a key created by keygen")]
+    Sign(SignCommand),
+    #[command(about = "Manage the public keys trusted for signed templates")]
+    Trust(TrustCommand),
+    #[command(about = "Manage the registries templates can be searched and fetched from")]
+    Registry(RegistryCommand),
+    #[command(about = "Search the added registries for templates")]
+    Search(SearchCommand),
+    #[command(about = "Serve the local templates over http as a registry")]
+    Serve(ServeCommand),
+    #[command(about = "Upload a template to a registry served by foldr serve")]
+    Publish(PublishCommand),
+    #[command(
+        about = "Fetch templates again from the url they were fetched from, storing a new version only when they changed"
+    )]
+    Refresh(RefreshCommand),
+    #[command(about = "Manage the cache of downloaded remote templates used when offline")]
+    Cache(CacheCommand),
+    #[command(about = "Write templates into a single pack file, to move them to another machine")]
+    Export(ExportCommand),
+    #[command(about = "Import the templates of a pack file written by foldr export")]
+    Import(ImportCommand),
+    #[command(
+        about = "Back up the templates, config, registries and trusted keys into a single file"
+    )]
+    Backup(BackupCommand),
+    #[command(about = "Restore the templates, config, registries and trusted keys from a backup")]
+    Restore(RestoreCommand),
+    #[command(about = "Manage the template store")]
+    Store(StoreCommand),
     #[cfg(feature = "tui")]
     #[command(about = "")]
     Tui,
@@ -46,11 +88,29 @@ pub fn run(command: Command, config: Config) -> Result<()> {
         Command::Update(update_command) => update_command.run(config),
         Command::Purge(purge_command) => purge_command.run(config),
         Command::Delete(delete_command) => delete_command.run(config),
+        Command::Gc(gc_command) => gc_command.run(config),
+        Command::Archive(archive_command) => archive_command.run(config),
+        Command::Verify(verify_command) => verify_command.run(config),
+        Command::Keygen(keygen_command) => keygen_command.run(config),
+        Command::Sign(sign_command) => sign_command.run(config),
+        Command::Trust(trust_command) => trust_command.run(config),
+        Command::Registry(registry_command) => registry_command.run(config),
+        Command::Search(search_command) => search_command.run(config),
+        Command::Serve(serve_command) => serve_command.run(config),
+        Command::Publish(publish_command) => publish_command.run(config),
+        Command::Refresh(refresh_command) => refresh_command.run(config),
+        Command::Cache(cache_command) => cache_command.run(config),
+        Command::Export(export_command) => export_command.run(config),
+        Command::Import(import_command) => import_command.run(config),
+        Command::Backup(backup_command) => backup_command.run(config),
+        Command::Restore(restore_command) => restore_command.run(config),
+        Command::Store(store_command) => store_command.run(config),
         #[cfg(feature = "tui")]
         Command::Tui => todo!(),
         Command::Config(config_command) => config_command.generate_config(),
     };
 }
+#[derive(Debug)]
 pub struct CommandError {
     pub message: String,
 }
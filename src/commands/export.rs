@@ -0,0 +1,73 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use clap::Args;
+
+use crate::{
+    config::{Config, ExpandablePathBuf},
+    packs::TemplatePack,
+    templates::Template,
+};
+
+use super::command::{Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct ExportCommand {
+    #[arg(help = "The templates to export. Exports every template when none are passed")]
+    pub template_names: Vec<String>,
+    #[arg(short, long, help = "The pack file to write, like templates.foldrpack")]
+    pub output: PathBuf,
+    #[arg(
+        long,
+        help = "Only export the most recent version of every template, instead of all versions"
+    )]
+    pub latest: bool,
+}
+
+impl RunCommand for ExportCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let mut selected: BTreeMap<(String, _), Template> = BTreeMap::new();
+        for template in Template::get_existing(&config)? {
+            if !self.template_names.is_empty() && !self.template_names.contains(&template.info.name)
+            {
+                continue;
+            }
+            selected.insert(
+                (template.info.name.clone(), template.info.iteration),
+                template,
+            );
+        }
+        for name in &self.template_names {
+            if !selected.keys().any(|(n, _)| n == name) {
+                return Err(error(&format!("Template not found: {}", name)));
+            }
+        }
+        if selected.is_empty() {
+            return Err(error("There are no templates to export"));
+        }
+        let mut templates: Vec<Template> = selected.into_values().collect();
+        if self.latest {
+            // Versions of a template are sorted, so the last one of every name is the most recent
+            templates = templates
+                .chunk_by(|a, b| a.info.name == b.info.name)
+                .filter_map(|versions| versions.last().cloned())
+                .collect();
+        }
+
+        let output = self.output.expand();
+        let size = TemplatePack::write(&config, &templates, &output)?;
+        for template in &templates {
+            println!(
+                "Exported {} version {}",
+                template.info.name, template.info.iteration
+            );
+        }
+        println!(
+            "Wrote {} template versions to {}\nSize: {}\nSha256: {}",
+            templates.len(),
+            output.to_string_lossy(),
+            bytesize::ByteSize::b(size),
+            TemplatePack::hash(&output)?
+        );
+        return Ok(());
+    }
+}
@@ -1,45 +1,93 @@
 use clap::Args;
 
-use crate::{commands::command::error, config::Config, network::NetworkUtil, templates::Template};
+use crate::{
+    commands::command::error, config::Config, git::GitSource, network::NetworkUtil,
+    registry::Registry, templates::Template,
+};
 
 use super::command::{Result, RunCommand};
 
 #[derive(Args, Debug)]
 pub struct FetchCommand {
-    #[arg(help = "The endpoint to download the template from. Must be http(s)")]
+    #[arg(
+        help = "The endpoint to download the template from. Must be http(s), a git repository as git+<url>#<ref>:<subdirectory>, where the ref and subdirectory are optional, or a template in an added registry as <registry>/<name>[@version]"
+    )]
     pub endpoint: String,
-    #[arg(help = "The name used to store the downloaded template")]
-    pub template_name: String,
+    #[arg(
+        help = "The name used to store the downloaded template. Defaults to the name in the registry when fetching from a registry"
+    )]
+    pub template_name: Option<String>,
     #[arg(
         short,
         long,
         help = "If set, update an already existing template with the fetched template. Does nothing if there is no existing template with the same name"
     )]
     pub update: bool,
+    #[arg(
+        long,
+        help = "Expected sha256 of the downloaded template file. The fetch is aborted when it doesn't match"
+    )]
+    pub sha256: Option<String>,
+    #[arg(
+        long,
+        help = "Remove the single top level directory all files of a downloaded archive are in, like in GitHub release tarballs"
+    )]
+    pub strip_top_level: bool,
 }
 
 impl RunCommand for FetchCommand {
     fn run(&self, config: Config) -> Result<()> {
-        let existing = Template::get_existing_by_name(&config, &self.template_name)?;
+        let mut endpoint = self.endpoint.clone();
+        let mut sha256 = self.sha256.clone();
+        let mut template_name = self.template_name.clone();
+        let mut reference = None;
+        if let Some((registry, name, iteration)) = Registry::parse_reference(&self.endpoint)? {
+            let entry = Registry::resolve(&config, &registry, &name, iteration)?;
+            println!(
+                "Found {} version {} in registry {}",
+                entry.name, entry.iteration, registry
+            );
+            endpoint = entry.url;
+            sha256 = sha256.or(entry.sha256);
+            template_name = template_name.or(Some(entry.name));
+            // Pinned versions never change, so only track the registry when no version was asked for
+            if iteration.is_none() {
+                reference = Some(format!("{}/{}", registry, name));
+            }
+        }
+        let Some(template_name) = template_name else {
+            return Err(error(
+                "A template name is required when fetching from an http endpoint",
+            ));
+        };
+        let existing = Template::get_existing_by_name(&config, &template_name)?;
+        if existing.is_some() && !self.update {
+            return Err(error(
+                "Template name already in use, use --update to update",
+            ));
+        }
+        let iteration = existing.map_or(1, |existing| existing.info.iteration + 1);
         let template;
-        if let Some(existing) = existing {
-            if !self.update {
+        if GitSource::is_git_source(&endpoint) {
+            if sha256.is_some() || self.strip_top_level {
                 return Err(error(
-                    "Template name already in use, use --update to update",
+                    "--sha256 and --strip-top-level can only be used with http templates",
                 ));
             }
-            template = NetworkUtil::fetch_template(
+            template = GitSource::parse(&config, &endpoint)?.fetch_template(
                 &config,
-                self.endpoint.clone(),
-                self.template_name.clone(),
-                existing.info.iteration + 1,
+                &template_name,
+                iteration,
             )?;
         } else {
             template = NetworkUtil::fetch_template(
                 &config,
-                self.endpoint.clone(),
-                self.template_name.clone(),
-                1,
+                endpoint,
+                template_name,
+                iteration,
+                sha256.as_deref(),
+                self.strip_top_level,
+                reference,
             )?;
         }
 
@@ -0,0 +1,37 @@
+use bytesize::ByteSize;
+use clap::Args;
+
+use crate::{blobs::BlobStore, config::Config};
+
+use super::command::{Result, RunCommand};
+
+#[derive(Args, Debug)]
+pub struct GcCommand {
+    #[arg(
+        short,
+        long,
+        help = "Only report which blobs would be removed, without removing them"
+    )]
+    pub dry_run: bool,
+}
+
+impl RunCommand for GcCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let (removed, freed) = BlobStore::gc(&config, self.dry_run)?;
+        if removed == 0 {
+            println!("No unreferenced blobs found");
+            return Ok(());
+        }
+        println!(
+            "{} {} unreferenced blobs, freeing {}",
+            if self.dry_run {
+                "Would remove"
+            } else {
+                "Removed"
+            },
+            removed,
+            ByteSize::b(freed)
+        );
+        return Ok(());
+    }
+}
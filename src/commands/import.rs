@@ -0,0 +1,93 @@
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use clap::Args;
+use zip::ZipArchive;
+
+use crate::{
+    config::{Config, ExpandablePathBuf},
+    packs::{OnConflict, PackEntry, TemplatePack},
+    templates::Template,
+};
+
+use super::command::{Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct ImportCommand {
+    #[arg(help = "The pack file to import, written by foldr export")]
+    pub pack: PathBuf,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = OnConflict::Skip,
+        help = "What to do with template versions that already exist"
+    )]
+    pub on_conflict: OnConflict,
+}
+
+impl RunCommand for ImportCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let (mut zip, index) = TemplatePack::open(&self.pack.expand())?;
+        let mut imported = 0;
+        let mut failed = 0;
+        for entry in &index.templates {
+            match self.import(&config, &mut zip, entry) {
+                Ok(Some(template)) => {
+                    imported += 1;
+                    println!(
+                        "{} version {}: imported as version {}",
+                        entry.name, entry.iteration, template.info.iteration
+                    );
+                }
+                Ok(None) => println!(
+                    "{} version {}: skipped, it already exists",
+                    entry.name, entry.iteration
+                ),
+                Err(e) => {
+                    failed += 1;
+                    println!(
+                        "{} version {}: FAILED: {}",
+                        entry.name, entry.iteration, e.message
+                    );
+                }
+            }
+        }
+        println!(
+            "Imported {} of {} template versions",
+            imported,
+            index.templates.len()
+        );
+        if failed > 0 {
+            return Err(error(&format!(
+                "{} template versions failed to import",
+                failed
+            )));
+        }
+        return Ok(());
+    }
+}
+
+impl ImportCommand {
+    /// Import a single template version. Returns None when it was skipped
+    fn import(
+        &self,
+        config: &Config,
+        zip: &mut ZipArchive<BufReader<File>>,
+        entry: &PackEntry,
+    ) -> Result<Option<Template>> {
+        let existing =
+            Template::get_existing_by_name_and_iteration(config, &entry.name, entry.iteration)?;
+        if existing.is_some() && self.on_conflict == OnConflict::Skip {
+            return Ok(None);
+        }
+        let file = TemplatePack::extract(zip, entry)?;
+        let mut iteration = entry.iteration;
+        if existing.is_some() && self.on_conflict == OnConflict::Renumber {
+            iteration = Template::get_existing_by_name(config, &entry.name)?
+                .map_or(1, |latest| latest.info.iteration + 1);
+        }
+        let replace = existing
+            .as_ref()
+            .filter(|_| self.on_conflict == OnConflict::Overwrite);
+        return TemplatePack::store(config, entry, file, iteration, replace).map(Some);
+    }
+}
@@ -0,0 +1,29 @@
+use clap::Args;
+
+use crate::{config::Config, signing::Signing};
+
+use super::command::{Result, RunCommand};
+
+#[derive(Args, Debug)]
+pub struct KeygenCommand {
+    #[arg(
+        short,
+        long,
+        default_value = "default",
+        help = "The name to store the key under in ~/.foldr/keys"
+    )]
+    pub name: String,
+    #[arg(long, help = "Overwrite an existing key with the same name")]
+    pub force: bool,
+}
+
+impl RunCommand for KeygenCommand {
+    fn run(&self, _config: Config) -> Result<()> {
+        let public_key = Signing::generate_key(&self.name, self.force)?;
+        println!(
+            "Generated signing key {}\nPublic key: {}\nShare the public key so others can trust templates you sign with foldr trust add",
+            self.name, public_key
+        );
+        return Ok(());
+    }
+}
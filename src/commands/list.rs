@@ -32,10 +32,20 @@ impl RunCommand for ListCommand {
             .chars()
             .count()
             .clamp(20, usize::MAX);
+        // Which store a template comes from only matters when there is more than one
+        let show_store = config.template_stores().len() > 1;
+        let store = |template: &Template| {
+            if show_store {
+                format!("  {}", template.store_dir().to_string_lossy())
+            } else {
+                String::new()
+            }
+        };
         println!(
-            "{:<width$}{:<3}",
+            "{:<width$}{:<3}{}",
             "name",
             "version",
+            if show_store { "  store" } else { "" },
             width = longest_template_name,
         );
         println!("{}", "-".repeat(longest_template_name + 3));
@@ -51,23 +61,21 @@ impl RunCommand for ListCommand {
         if self.flatten {
             for template in templates {
                 println!(
-                    "{:<width$}{:<3}",
+                    "{:<width$}{:<7}{}",
                     template.info.name,
                     template.info.iteration,
+                    store(&template),
                     width = longest_template_name
                 );
             }
         } else {
             for (key, group) in &templates.iter().chunk_by(|t| &t.info.name) {
+                let latest = *group.collect::<Vec<&Template>>().last().unwrap();
                 println!(
-                    "{:<width$}{:<3}",
+                    "{:<width$}{:<7}{}",
                     key.to_string(),
-                    group
-                        .collect::<Vec<&Template>>()
-                        .iter()
-                        .map(|t| t.info.iteration)
-                        .last()
-                        .unwrap(),
+                    latest.info.iteration,
+                    store(latest),
                     width = longest_template_name
                 );
             }
@@ -1,10 +1,27 @@
+pub mod archive;
+pub mod backup;
+pub mod cache;
 pub mod command;
 pub mod config;
 pub mod delete;
+pub mod export;
 pub mod fetch;
+pub mod gc;
+pub mod import;
+pub mod keygen;
 pub mod list;
 pub mod new;
+pub mod publish;
 pub mod purge;
+pub mod refresh;
+pub mod registry;
+pub mod restore;
 pub mod save;
+pub mod search;
+pub mod serve;
 pub mod show;
+pub mod sign;
+pub mod store;
+pub mod trust;
 pub mod update;
+pub mod verify;
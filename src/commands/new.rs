@@ -3,10 +3,12 @@ use std::path::PathBuf;
 use clap::Args;
 
 use crate::{
+    archives::OutputArchive,
     cli::CliUtils,
     config::{Config, ExpandablePathBuf},
+    git::GitSource,
     network::NetworkUtil,
-    templates::Template,
+    templates::{SpawnTarget, Template},
 };
 
 use super::command::{Iteration, RunCommand, error};
@@ -14,7 +16,7 @@ use super::command::{Iteration, RunCommand, error};
 #[derive(Args, Debug)]
 pub struct NewCommand {
     #[arg(
-        help = "The template to spawn. Can also be a remote template fetched over http. If no template is passed, a fuzzy finder will open instead"
+        help = "The template to spawn. Can also be a remote template fetched over http, or a git repository as git+<url>#<ref>:<subdirectory>, where the ref and subdirectory are optional. If no template is passed, a fuzzy finder will open instead"
     )]
     pub template_name: Option<String>,
     #[arg(
@@ -25,6 +27,28 @@ pub struct NewCommand {
     pub iteration: Option<Iteration>,
     #[arg(help = "The output path to spawn the template into. Defaults to the current directory")]
     pub path: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Spawn the template even if its contents don't match the hashes recorded when it was saved"
+    )]
+    pub force: bool,
+    #[arg(
+        long,
+        help = "Expected sha256 of a remote template file. Nothing is spawned when it doesn't match"
+    )]
+    pub sha256: Option<String>,
+    #[arg(
+        long,
+        help = "Remove the single top level directory all files of a downloaded archive are in, like in GitHub release tarballs"
+    )]
+    pub strip_top_level: bool,
+    #[arg(
+        short,
+        long,
+        conflicts_with = "path",
+        help = "Write the spawned files into a .zip, .tar, .tar.gz or .tar.zst archive instead of a directory. Pass - to write a tar stream to stdout"
+    )]
+    pub output_archive: Option<PathBuf>,
 }
 
 impl RunCommand for NewCommand {
@@ -37,20 +61,39 @@ impl RunCommand for NewCommand {
             name = self.template_name.clone().unwrap();
         }
 
-        let mut spawn_path = self.path.clone().unwrap_or("./".into());
-        spawn_path = spawn_path.expand();
+        let target = match &self.output_archive {
+            Some(archive) => SpawnTarget::Archive(OutputArchive::new(&archive.expand())?),
+            None => SpawnTarget::Directory(self.path.clone().unwrap_or("./".into()).expand()),
+        };
+        if GitSource::is_git_source(&name) {
+            if self.sha256.is_some() || self.strip_top_level {
+                return Err(error(
+                    "--sha256 and --strip-top-level can only be used with http templates",
+                ));
+            }
+            GitSource::parse(&config, &name)?.fetch_and_spawn_template(&config, &target)?;
+            Self::report(&target, &format!("Spawned template {} into", name));
+            return Ok(());
+        }
         if let Some(name) = self.template_name.clone() {
             if name.starts_with("http://") || name.starts_with("https://") {
                 // Fetch from remote
-                NetworkUtil::fetch_and_spawn_template(&config, name.clone(), spawn_path.clone())?;
-                println!(
-                    "Spawned template {} into {}",
-                    name,
-                    spawn_path.to_string_lossy()
-                );
+                NetworkUtil::fetch_and_spawn_template(
+                    &config,
+                    name.clone(),
+                    &target,
+                    self.sha256.as_deref(),
+                    self.strip_top_level,
+                )?;
+                Self::report(&target, &format!("Spawned template {} into", name));
                 return Ok(());
             }
         }
+        if self.sha256.is_some() || self.strip_top_level {
+            return Err(error(
+                "--sha256 and --strip-top-level can only be used with remote templates",
+            ));
+        }
         let existing = if let Some(iteration) = self.iteration {
             Template::get_existing_by_name_and_iteration(&config, &name, iteration)?
         } else {
@@ -62,12 +105,20 @@ impl RunCommand for NewCommand {
         }
         let existing = existing.unwrap();
 
-        existing.spawn(&spawn_path);
-        println!(
-            "Template {} created at {}",
-            &name,
-            spawn_path.to_string_lossy()
-        );
+        existing.spawn(&config, &target, self.force)?;
+        Self::report(&target, &format!("Template {} created at", name));
         return Ok(());
     }
 }
+
+impl NewCommand {
+    /// Print where the template was spawned. Goes to stderr when the archive is written to stdout
+    fn report(target: &SpawnTarget, message: &str) {
+        match target {
+            SpawnTarget::Archive(archive) if archive.is_stdout() => {
+                eprintln!("{} {}", message, target.describe())
+            }
+            _ => println!("{} {}", message, target.describe()),
+        }
+    }
+}
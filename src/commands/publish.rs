@@ -0,0 +1,105 @@
+use std::fs::File;
+
+use clap::Args;
+use tempfile::NamedTempFile;
+
+use crate::{
+    config::Config, globals, network::NetworkUtil, registry::Registry, signing::Signing,
+    templates::Template,
+};
+
+use super::command::{Iteration, Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct PublishCommand {
+    #[arg(
+        help = "The template to publish, as <name> or <name>@<version>. Defaults to the most recent version"
+    )]
+    pub reference: String,
+    #[arg(
+        long,
+        help = "The registry to publish to. It must be served by foldr serve with --allow-write"
+    )]
+    pub to: String,
+}
+
+impl RunCommand for PublishCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let (name, iteration) = match self.reference.rsplit_once('@') {
+            Some((name, iteration)) => {
+                let iteration = iteration
+                    .parse::<Iteration>()
+                    .map_err(|_| error(&format!("Invalid template version {}", iteration)))?;
+                (name, Some(iteration))
+            }
+            None => (self.reference.as_str(), None),
+        };
+        let template = if let Some(iteration) = iteration {
+            Template::get_existing_by_name_and_iteration(&config, name, iteration)?
+        } else {
+            Template::get_existing_by_name(&config, name)?
+        };
+        let Some(template) = template else {
+            return Err(error("Template or template version not found"));
+        };
+        let registries = Registry::get_all()?;
+        let Some(registry_url) = registries.get(&self.to) else {
+            return Err(error(&format!(
+                "No registry named {}. Add one with foldr registry add",
+                self.to
+            )));
+        };
+        let url = format!(
+            "{}/templates/{}/{}.{}",
+            Registry::base_url(registry_url),
+            Registry::encode_name(&template.info.name),
+            template.info.iteration,
+            globals::FOLDR_TEMPLATE_EXTENSION
+        );
+
+        let archive =
+            NamedTempFile::new().map_err(|_| error("IO error creating temporary archive"))?;
+        template.write_archive(&config, &archive.path().to_path_buf())?;
+        let file = File::open(archive.path()).map_err(|_| error("IO error opening archive"))?;
+        println!(
+            "Publishing {} version {} to {}",
+            template.info.name, template.info.iteration, self.to
+        );
+        Self::check_response(NetworkUtil::upload(&config, &url, file)?, &self.to)?;
+
+        if let Some(signature) = Signing::read_signature(&template)? {
+            let signature_url = format!("{}.{}", url, globals::FOLDR_SIGNATURE_EXTENSION);
+            let body = serde_json::to_vec_pretty(&signature).unwrap();
+            Self::check_response(
+                NetworkUtil::upload(&config, &signature_url, &body[..])?,
+                &self.to,
+            )?;
+            println!("Published signature");
+        }
+        println!(
+            "Published {} version {} to {}",
+            template.info.name, template.info.iteration, self.to
+        );
+        return Ok(());
+    }
+}
+
+impl PublishCommand {
+    fn check_response((status, message): (u16, String), registry: &str) -> Result<()> {
+        return match status {
+            200..=299 => Ok(()),
+            409 => Err(error(&format!(
+                "This version already exists in registry {}. Save a new version to publish changes",
+                registry
+            ))),
+            401 | 403 => Err(error(&format!(
+                "Not authorized to publish to registry {}. Set a write token for its host in the credentials config key or a FOLDR_TOKEN_<HOST> environment variable",
+                registry
+            ))),
+            _ => Err(error(&format!(
+                "Registry {} refused the upload ({}): {}",
+                registry, status, message
+            ))),
+        };
+    }
+}
@@ -15,6 +15,7 @@ impl RunCommand for PurgeCommand {
     fn run(&self, config: Config) -> Result<(), super::command::CommandError> {
         let existing = Template::get_existing(&config)?;
         let mut entries_deleted = 0;
+        let mut entries_kept = 0;
         for (key, group) in &existing.iter().chunk_by(|t| t.info.name.clone()) {
             if let Some(name) = &self.template_name {
                 if key != name.clone() {
@@ -33,6 +34,18 @@ impl RunCommand for PurgeCommand {
                 continue;
             }
             for i in 0..all.len() - 1 {
+                // Older versions in a read only template store stay, they belong to the store
+                let template = all.get(i).unwrap();
+                if template.ensure_writable(&config).is_err() {
+                    println!(
+                        "Kept {} version {}, its template store {} is read only",
+                        key,
+                        template.info.iteration,
+                        template.store_dir().to_string_lossy()
+                    );
+                    entries_kept += 1;
+                    continue;
+                }
                 Template::delete_by_name_and_iteration(
                     &config,
                     &key,
@@ -41,6 +54,9 @@ impl RunCommand for PurgeCommand {
                 entries_deleted += 1;
             }
         }
+        if entries_deleted == 0 && entries_kept > 0 {
+            return Err(error("All older versions are in read only template stores"));
+        }
         if entries_deleted == 0 {
             return Err(error("Template not found or no templates to purge"));
         }
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use clap::Args;
+
+use crate::{
+    config::Config, network::NetworkUtil, registry::Registry, sources::TemplateSource,
+    templates::Template,
+};
+
+use super::command::{Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct RefreshCommand {
+    #[arg(help = "Template to refresh. Defaults to all templates that were fetched from a url")]
+    pub template_name: Option<String>,
+}
+
+impl RefreshCommand {
+    /// Fetch the source of the most recent version of a template again. Returns the new version
+    /// when the source changed
+    fn refresh(
+        config: &Config,
+        template: &Template,
+        source: &TemplateSource,
+    ) -> Result<Option<Template>> {
+        let mut endpoint = source.url.clone();
+        let mut sha256 = None;
+        if let Some(reference) = &source.reference {
+            let Some((registry, name, _)) = Registry::parse_reference(reference)? else {
+                return Err(error(&format!(
+                    "Template was fetched from {}, but that registry was removed",
+                    reference
+                )));
+            };
+            let entry = Registry::resolve(config, &registry, &name, None)?;
+            if entry.sha256.as_ref() == Some(&source.sha256) {
+                return Ok(None);
+            }
+            endpoint = entry.url;
+            sha256 = entry.sha256;
+        }
+        return NetworkUtil::refresh_template(
+            config,
+            template,
+            source,
+            endpoint,
+            sha256.as_deref(),
+        );
+    }
+}
+
+impl RunCommand for RefreshCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        // Only the most recent version of each template is refreshed
+        let mut latest: BTreeMap<String, Template> = BTreeMap::new();
+        for template in Template::get_existing(&config)? {
+            if self
+                .template_name
+                .as_ref()
+                .is_some_and(|name| template.info.name != *name)
+            {
+                continue;
+            }
+            if latest
+                .get(&template.info.name)
+                .is_none_or(|t| t.info.iteration < template.info.iteration)
+            {
+                latest.insert(template.info.name.clone(), template);
+            }
+        }
+        if self.template_name.is_some() && latest.is_empty() {
+            return Err(error("Template not found"));
+        }
+
+        let mut refreshed = 0;
+        let mut failed = 0;
+        for template in latest.values() {
+            let Some(source) = TemplateSource::read(template)? else {
+                if self.template_name.is_some() {
+                    return Err(error(
+                        "Template was not fetched from a url, so there is nothing to refresh it from",
+                    ));
+                }
+                continue;
+            };
+            refreshed += 1;
+            match Self::refresh(&config, template, &source) {
+                Ok(Some(new)) => println!(
+                    "{}: updated to version {}",
+                    new.info.name, new.info.iteration
+                ),
+                Ok(None) => println!(
+                    "{}: up to date at version {}",
+                    template.info.name, template.info.iteration
+                ),
+                Err(e) => {
+                    failed += 1;
+                    println!("{}: FAILED: {}", template.info.name, e.message);
+                }
+            }
+        }
+        if refreshed == 0 {
+            println!("No fetched templates to refresh");
+        }
+        if failed > 0 {
+            return Err(error(&format!("{} templates failed to refresh", failed)));
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,64 @@
+use clap::{Args, Subcommand};
+
+use crate::{config::Config, network::NetworkUtil, registry::Registry};
+
+use super::command::{Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct RegistryCommand {
+    #[command(subcommand)]
+    pub action: RegistryAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RegistryAction {
+    #[command(about = "Add a registry to search and fetch templates from")]
+    Add {
+        #[arg(
+            help = "The name used to refer to the registry, like in foldr fetch <registry>/<name>"
+        )]
+        name: String,
+        #[arg(help = "Url of the index.json of the registry, or of the directory it is in")]
+        url: String,
+    },
+    #[command(about = "Remove a registry")]
+    Remove {
+        #[arg(help = "The name the registry was added under")]
+        name: String,
+    },
+    #[command(about = "List all added registries")]
+    List,
+}
+
+impl RunCommand for RegistryCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let mut registries = Registry::get_all()?;
+        match &self.action {
+            RegistryAction::Add { name, url } => {
+                if name.contains('/') || name.contains('@') {
+                    return Err(error("Registry names can't contain / or @"));
+                }
+                NetworkUtil::check_endpoint(&config, url)?;
+                registries.insert(name.clone(), url.clone());
+                Registry::save_all(&registries)?;
+                println!("Added registry {}", name);
+            }
+            RegistryAction::Remove { name } => {
+                if registries.remove(name).is_none() {
+                    return Err(error(&format!("No registry named {}", name)));
+                }
+                Registry::save_all(&registries)?;
+                println!("Removed registry {}", name);
+            }
+            RegistryAction::List => {
+                if registries.is_empty() {
+                    println!("No registries added yet. Add one using foldr registry add");
+                }
+                for (name, url) in registries {
+                    println!("{}: {}", name, url);
+                }
+            }
+        }
+        return Ok(());
+    }
+}
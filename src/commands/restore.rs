@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use clap::Args;
+
+use crate::{
+    backups::Backup,
+    config::{Config, ExpandablePathBuf},
+    templates::Template,
+};
+
+use super::command::{Result, RunCommand};
+
+#[derive(Args, Debug)]
+pub struct RestoreCommand {
+    #[arg(help = "The backup file written by foldr backup")]
+    pub backup: PathBuf,
+    #[arg(
+        long,
+        help = "Replace the current templates, keys, trusted keys and registries when they exist already"
+    )]
+    pub force: bool,
+}
+
+impl RunCommand for RestoreCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let info = Backup::restore(&config, &self.backup.expand(), self.force)?;
+        let config = Config::load(Some(config.file.clone()))?;
+        println!(
+            "Restored backup made by foldr {}\nTemplates: {}",
+            info.foldr_version,
+            Template::get_existing(&config)?.len()
+        );
+        return Ok(());
+    }
+}
@@ -1,20 +1,112 @@
-use std::path::PathBuf;
+use std::{fs::File, io, path::PathBuf};
 
 use clap::Args;
 
 use crate::{
+    archives::ForeignArchive,
     config::{Config, ExpandablePathBuf},
+    git::GitTree,
+    globals::FOLDR_MANIFEST_FILE,
     templates::Template,
+    zip::{ArchiveOptions, Compression, SymlinkMode},
 };
 
 use super::command::{RunCommand, error};
 
 #[derive(Args, Debug)]
 pub struct SaveCommand {
-    #[arg(help = "The directory to save as a template")]
+    #[arg(
+        help = "The directory to save as a template. With --from-archive, a zip, tar, tar.gz or tar.zst archive instead. Pass - to read a tar archive from stdin"
+    )]
     pub directory: PathBuf,
     #[arg(help = "The name for the template. Must be unique")]
     pub name: String,
+    #[arg(
+        long,
+        conflicts_with_all = ["symlinks", "preserve_mtime", "reproducible", "compression", "compression_level"],
+        help = "Save the contents of an archive instead of a directory"
+    )]
+    pub from_archive: bool,
+    #[arg(
+        long,
+        help = "Remove the single top level directory all files of the archive are in"
+    )]
+    pub strip_top_level: bool,
+    #[arg(
+        long,
+        conflicts_with = "from_archive",
+        help = "Save only the files tracked by git, and record the commit in the template"
+    )]
+    pub git_tracked: bool,
+    #[arg(
+        long,
+        requires = "git_tracked",
+        help = "Save the tracked files at a commit, branch or tag instead of the working tree"
+    )]
+    pub git_ref: Option<String>,
+    #[command(flatten)]
+    pub archive: ArchiveArgs,
+}
+
+/// Arguments controlling how a directory is written into a template file. Shared by save and update
+#[derive(Args, Debug)]
+pub struct ArchiveArgs {
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = SymlinkMode::Keep,
+        help = "Whether symlinks are stored as links or replaced by the files they point to"
+    )]
+    pub symlinks: SymlinkMode,
+    #[arg(
+        long,
+        conflicts_with = "reproducible",
+        help = "Record the modification time of every file and restore it when spawning"
+    )]
+    pub preserve_mtime: bool,
+    #[arg(
+        long,
+        help = "Use a fixed timestamp and sorted entry order. Saving the same directory again gives the same content digest, and an identical template file for the same name and version"
+    )]
+    pub reproducible: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "Compression method for the template file. Defaults to the compression key in the config"
+    )]
+    pub compression: Option<Compression>,
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        help = "Compression level for the chosen compression method. Defaults to the compression_level key in the config"
+    )]
+    pub compression_level: Option<i64>,
+}
+
+impl ArchiveArgs {
+    /// Whether any of the arguments was changed from its default
+    pub fn is_set(&self) -> bool {
+        return self.symlinks != SymlinkMode::Keep
+            || self.preserve_mtime
+            || self.reproducible
+            || self.compression.is_some()
+            || self.compression_level.is_some();
+    }
+
+    pub fn to_options(&self, config: &Config) -> ArchiveOptions {
+        let mut options = ArchiveOptions::from_config(config);
+        options.symlinks = self.symlinks;
+        options.preserve_mtime = self.preserve_mtime;
+        options.reproducible = self.reproducible;
+        if let Some(compression) = self.compression {
+            options.compression = compression;
+            options.compression_level = None;
+        }
+        if self.compression_level.is_some() {
+            options.compression_level = self.compression_level;
+        }
+        return options;
+    }
 }
 
 impl RunCommand for SaveCommand {
@@ -26,12 +118,82 @@ impl RunCommand for SaveCommand {
                 &self.name
             )));
         }
-        let result = Template::save(&config, &self.directory.expand(), &self.name, 1)?;
+        let from_stdin = self.directory.as_os_str() == "-";
+        if self.strip_top_level && !self.from_archive && !from_stdin {
+            return Err(error(
+                "--strip-top-level can only be used with --from-archive or -",
+            ));
+        }
+        if self.git_tracked && from_stdin {
+            return Err(error("--git-tracked can't be used when reading from stdin"));
+        }
+        // Archives are stored as they are, so these would never be applied
+        if from_stdin && self.archive.is_set() {
+            return Err(error(
+                "--symlinks, --preserve-mtime, --reproducible, --compression and --compression-level can't be used when reading from stdin",
+            ));
+        }
+        if self.from_archive || from_stdin {
+            let archive = if from_stdin {
+                ForeignArchive::from_stream(&config, io::stdin().lock(), self.strip_top_level)?
+            } else {
+                let file = File::open(self.directory.expand()).map_err(|_| {
+                    error(&format!(
+                        "Unable to open archive {}",
+                        self.directory.to_string_lossy()
+                    ))
+                })?;
+                ForeignArchive::to_template_file(&config, &file, self.strip_top_level)?
+                    .unwrap_or(file)
+            };
+            let result = Template::store(
+                &config,
+                self.name.clone(),
+                1,
+                archive,
+                vec![FOLDR_MANIFEST_FILE.into()],
+            )?;
+            println!(
+                "Created template: {}\nSize: {}",
+                result.filename.file_name().unwrap().to_string_lossy(),
+                result.filesize
+            );
+            return Ok(());
+        }
+        let mut options = self.archive.to_options(&config);
+        let mut directory = self.directory.expand();
+        // Keeps the files exported from a git ref until the template is saved
+        let mut _export = None;
+        if self.git_tracked {
+            let provenance = match &self.git_ref {
+                Some(reference) => {
+                    let (export, provenance) = GitTree::export(&directory, reference)?;
+                    directory = export.path().to_path_buf();
+                    _export = Some(export);
+                    provenance
+                }
+                None => {
+                    let (files, provenance) = GitTree::tracked_files(&directory)?;
+                    options.include = Some(files);
+                    provenance
+                }
+            };
+            println!("Saving files tracked at commit {}", provenance.commit);
+            if provenance.modified {
+                println!("Uncommitted changes to tracked files are included");
+            }
+            options.git = Some(provenance);
+        }
+        let result = Template::save(&config, &directory, &self.name, 1, &options)?;
         println!(
             "Created template: {}\nSize: {}",
-            result.filename.to_string_lossy(),
+            result.filename.file_name().unwrap().to_string_lossy(),
             result.filesize
         );
+        if options.reproducible {
+            println!("Sha256: {}", result.archive_hash()?);
+            println!("Digest: {}", result.info.digest.clone().unwrap_or_default());
+        }
         return Ok(());
     }
 }
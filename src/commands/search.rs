@@ -0,0 +1,78 @@
+use clap::Args;
+
+use crate::{
+    config::Config,
+    registry::{Registry, RegistryEntry},
+};
+
+use super::command::{Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct SearchCommand {
+    #[arg(help = "Text to look for in the names, descriptions and tags of templates")]
+    pub query: String,
+    #[arg(
+        short,
+        long,
+        help = "Only search this registry. Defaults to all added registries"
+    )]
+    pub registry: Option<String>,
+    #[arg(
+        short,
+        long,
+        help = "List every version of a matching template instead of only the most recent one"
+    )]
+    pub all: bool,
+}
+
+impl RunCommand for SearchCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let registries: Vec<String> = match &self.registry {
+            Some(registry) => vec![registry.clone()],
+            None => Registry::get_all()?.into_keys().collect(),
+        };
+        if registries.is_empty() {
+            return Err(error(
+                "No registries added yet. Add one using foldr registry add",
+            ));
+        }
+
+        let mut found = 0;
+        for registry in registries {
+            let (_, index) = match Registry::fetch_index(&config, &registry) {
+                Ok(index) => index,
+                // One unreachable registry shouldn't hide the results of the others
+                Err(e) if self.registry.is_none() => {
+                    println!("Skipping registry {}: {}", registry, e.message);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let mut matches: Vec<RegistryEntry> = index
+                .templates
+                .into_iter()
+                .filter(|e| e.matches(&self.query))
+                .collect();
+            matches.sort_by_key(|e| e.iteration);
+            matches.reverse();
+            matches.sort_by_key(|e| e.name.clone());
+            if !self.all {
+                matches.dedup_by_key(|e| e.name.clone());
+            }
+            for entry in matches {
+                found += 1;
+                println!("{}/{}@{}", registry, entry.name, entry.iteration);
+                if let Some(description) = &entry.description {
+                    println!("    {}", description);
+                }
+                if !entry.tags.is_empty() {
+                    println!("    tags: {}", entry.tags.join(", "));
+                }
+            }
+        }
+        if found == 0 {
+            println!("No templates found matching {}", self.query);
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,59 @@
+use std::env;
+
+use clap::Args;
+
+use crate::{config::Config, server::TemplateServer};
+
+use super::command::{Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct ServeCommand {
+    #[arg(
+        short,
+        long,
+        default_value = "127.0.0.1:8080",
+        help = "The address and port to listen on"
+    )]
+    pub bind: String,
+    #[arg(
+        long,
+        help = "Only serve clients sending this bearer token. Can also be set with the FOLDR_SERVE_TOKEN environment variable"
+    )]
+    pub token: Option<String>,
+    #[arg(
+        long,
+        help = "Accept templates published with foldr publish. Requires a write token"
+    )]
+    pub allow_write: bool,
+    #[arg(
+        long,
+        help = "Bearer token clients must send to publish templates. Can also be set with the FOLDR_SERVE_WRITE_TOKEN environment variable"
+    )]
+    pub write_token: Option<String>,
+}
+
+impl RunCommand for ServeCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let token = self
+            .token
+            .clone()
+            .or(env::var("FOLDR_SERVE_TOKEN").ok())
+            .filter(|t| !t.is_empty());
+        let write_token = if self.allow_write {
+            let write_token = self
+                .write_token
+                .clone()
+                .or(env::var("FOLDR_SERVE_WRITE_TOKEN").ok())
+                .filter(|t| !t.is_empty());
+            if write_token.is_none() {
+                return Err(error(
+                    "--allow-write requires a --write-token, so only trusted clients can publish",
+                ));
+            }
+            write_token
+        } else {
+            None
+        };
+        return TemplateServer::new(config, token, write_token).run(&self.bind);
+    }
+}
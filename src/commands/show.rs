@@ -24,7 +24,7 @@ impl RunCommand for ShowCommand {
             Template::get_existing_by_name(&config, &self.template_name)?
         };
         if let Some(template) = template {
-            let root = template.get_content_hierarchy();
+            let root = template.get_content_hierarchy()?;
             println!("{}", root);
         } else {
             return Err(error("Template or template version not found"));
@@ -0,0 +1,48 @@
+use clap::Args;
+
+use crate::{config::Config, signing::Signing, templates::Template};
+
+use super::command::{Iteration, Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct SignCommand {
+    #[arg(help = "The template to sign")]
+    pub template_name: String,
+    #[arg(
+        short,
+        long,
+        help = "The version of the template to sign. Defaults to the most recent version"
+    )]
+    pub iteration: Option<Iteration>,
+    #[arg(
+        short,
+        long,
+        default_value = "default",
+        help = "The name of the key to sign with, as created by foldr keygen"
+    )]
+    pub key: String,
+}
+
+impl RunCommand for SignCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let template = if let Some(iteration) = self.iteration {
+            Template::get_existing_by_name_and_iteration(&config, &self.template_name, iteration)?
+        } else {
+            Template::get_existing_by_name(&config, &self.template_name)?
+        };
+        let Some(template) = template else {
+            return Err(error("Template or template version not found"));
+        };
+        template.ensure_writable(&config)?;
+        let signature = Signing::sign(&template, &self.key)?;
+        println!(
+            "Signed template {} version {} with key {}\nSignature: {}",
+            template.info.name,
+            template.info.iteration,
+            self.key,
+            Signing::signature_path(&template).to_string_lossy()
+        );
+        println!("Public key: {}", signature.public_key);
+        return Ok(());
+    }
+}
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use clap::{Args, Subcommand};
+
+use crate::{config::Config, store::StoreMove};
+
+use super::command::{Result, RunCommand};
+
+#[derive(Args, Debug)]
+pub struct StoreCommand {
+    #[command(subcommand)]
+    pub action: StoreAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum StoreAction {
+    #[command(
+        about = "Move the template store to another directory and point template_dir in the config at it. Run it again to finish an interrupted move"
+    )]
+    Move {
+        #[arg(help = "The new directory for the template store. Must be empty or not exist yet")]
+        directory: PathBuf,
+    },
+}
+
+impl RunCommand for StoreCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        match &self.action {
+            StoreAction::Move { directory } => {
+                let store_move = StoreMove::start(&config, directory)?;
+                let moved = store_move.run(&config)?;
+                println!(
+                    "Moved {} template versions to {}",
+                    moved,
+                    store_move.to.to_string_lossy()
+                );
+            }
+        }
+        return Ok(());
+    }
+}
@@ -0,0 +1,65 @@
+use clap::{Args, Subcommand};
+
+use crate::{
+    config::Config,
+    signing::{Signing, from_hex},
+};
+
+use super::command::{Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct TrustCommand {
+    #[command(subcommand)]
+    pub action: TrustAction,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TrustAction {
+    #[command(about = "Trust templates signed by a public key")]
+    Add {
+        #[arg(help = "A name to remember the key by")]
+        name: String,
+        #[arg(help = "The hex encoded public key, as printed by foldr keygen")]
+        public_key: String,
+    },
+    #[command(about = "Stop trusting a public key")]
+    Remove {
+        #[arg(help = "The name the key was added under")]
+        name: String,
+    },
+    #[command(about = "List all trusted public keys")]
+    List,
+}
+
+impl RunCommand for TrustCommand {
+    fn run(&self, _config: Config) -> Result<()> {
+        let mut keys = Signing::trusted_keys()?;
+        match &self.action {
+            TrustAction::Add { name, public_key } => {
+                let public_key = public_key.trim().to_lowercase();
+                if from_hex(&public_key).map_or(true, |k| k.len() != 32) {
+                    return Err(error("Public key must be 32 hex encoded bytes"));
+                }
+                keys.insert(name.clone(), public_key);
+                Signing::save_trusted_keys(&keys)?;
+                println!("Trusted key {}", name);
+            }
+            TrustAction::Remove { name } => {
+                if keys.remove(name).is_none() {
+                    return Err(error(&format!("No trusted key named {}", name)));
+                }
+                Signing::save_trusted_keys(&keys)?;
+                println!("Removed trusted key {}", name);
+            }
+            TrustAction::List => {
+                if keys.is_empty() {
+                    println!("No trusted keys");
+                }
+                for (name, key) in keys {
+                    println!("{}: {}", name, key);
+                }
+            }
+        }
+        return Ok(());
+    }
+}
@@ -7,7 +7,10 @@ use crate::{
     templates::Template,
 };
 
-use super::command::{RunCommand, error};
+use super::{
+    command::{RunCommand, error},
+    save::ArchiveArgs,
+};
 
 #[derive(Args, Debug)]
 pub struct UpdateCommand {
@@ -15,23 +18,38 @@ pub struct UpdateCommand {
     pub template_name: String,
     #[arg(help = "Directory to update the template with")]
     pub directory: PathBuf,
+    #[command(flatten)]
+    pub archive: ArchiveArgs,
 }
 
 impl RunCommand for UpdateCommand {
     fn run(&self, config: Config) -> Result<(), super::command::CommandError> {
         let existing = Template::get_existing_by_name(&config, &self.template_name)?;
         if let Some(template) = existing {
-            let _result = Template::save(
+            let options = self.archive.to_options(&config);
+            let result = Template::save(
                 &config,
                 &self.directory.expand(),
                 &self.template_name,
                 template.info.iteration + 1,
+                &options,
             )?;
 
             println!(
                 "Updated template {} to version {}",
-                template.info.name, template.info.iteration
-            )
+                result.info.name, result.info.iteration
+            );
+            if options.reproducible {
+                println!("Sha256: {}", result.archive_hash()?);
+                println!("Digest: {}", result.info.digest.clone().unwrap_or_default());
+                // The file hash differs between versions, since the manifest records the version
+                if result.info.digest.is_some() && result.info.digest == template.info.digest {
+                    println!(
+                        "Contents are identical to version {}",
+                        template.info.iteration
+                    );
+                }
+            }
         } else {
             return Err(error(&format!(
                 "Template to update not found: {}",
@@ -0,0 +1,102 @@
+use clap::Args;
+
+use crate::{
+    config::Config,
+    signing::Signing,
+    templates::{Integrity, Template},
+};
+
+use super::command::{Iteration, Result, RunCommand, error};
+
+#[derive(Args, Debug)]
+pub struct VerifyCommand {
+    #[arg(help = "Template to verify. Defaults to all templates")]
+    pub template_name: Option<String>,
+    #[arg(
+        short,
+        long,
+        help = "When given, only verify the specified version of the template"
+    )]
+    pub iteration: Option<Iteration>,
+}
+
+impl VerifyCommand {
+    /// Report the signature of a template if it has one. Returns false when the signature is invalid
+    fn check_signature(template: &Template) -> bool {
+        let result = Signing::read_signature(template).and_then(|signature| {
+            let Some(signature) = signature else {
+                return Ok(None);
+            };
+            let Some(digest) = &template.info.digest else {
+                return Err(error("Template has a signature but no recorded digest"));
+            };
+            Signing::verify_signature(&signature, digest)?;
+            return Ok(Some(
+                Signing::trusted_name(&signature.public_key)?
+                    .unwrap_or(format!("unknown key {}", signature.public_key)),
+            ));
+        });
+        match result {
+            Ok(None) => return true,
+            Ok(Some(signer)) => println!("  signed by {}", signer),
+            Err(e) => {
+                println!("  signature FAILED: {}", e.message);
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+impl RunCommand for VerifyCommand {
+    fn run(&self, config: Config) -> Result<()> {
+        let templates: Vec<Template> = Template::get_existing(&config)?
+            .into_iter()
+            .filter(|t| {
+                self.template_name
+                    .as_ref()
+                    .is_none_or(|name| t.info.name == *name)
+                    && self.iteration.is_none_or(|i| t.info.iteration == i)
+            })
+            .collect();
+        if templates.is_empty() {
+            return Err(error("Template or template version not found"));
+        }
+
+        let mut failed = 0;
+        for template in templates {
+            match template.verify()? {
+                Integrity::Valid => {
+                    println!(
+                        "{} version {}: ok",
+                        template.info.name, template.info.iteration
+                    )
+                }
+                Integrity::Unrecorded => println!(
+                    "{} version {}: no hashes recorded, save it again to record them",
+                    template.info.name, template.info.iteration
+                ),
+                Integrity::Invalid(problems) => {
+                    failed += 1;
+                    println!(
+                        "{} version {}: FAILED",
+                        template.info.name, template.info.iteration
+                    );
+                    for problem in problems {
+                        println!("  {}", problem);
+                    }
+                }
+            }
+            if !Self::check_signature(&template) {
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(error(&format!(
+                "{} template versions failed verification",
+                failed
+            )));
+        }
+        return Ok(());
+    }
+}
@@ -1,25 +1,160 @@
-use std::path::PathBuf;
+use std::{collections::BTreeMap, fs, path::PathBuf};
 
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    blobs::StorageBackend,
+    commands::command::{Result, error},
+    credentials::HostCredentials,
+    globals,
+    zip::Compression,
+};
+
 #[derive(Serialize, Deserialize)]
+#[serde(default = "Config::defaults")]
 pub struct Config {
     /// Directory where template are stored
     pub template_dir: PathBuf,
+    /// More directories to look up templates in, like a read only team share. The versions of a template
+    /// are merged across template_dir and these directories. When the same version is in more than one
+    /// of them, template_dir wins, then these in order. New templates and versions are always written to
+    /// template_dir
+    pub template_dirs: Vec<PathBuf>,
     /// Whether to use the sqlite database as a cache to speed up template queries
     pub use_cache: bool,
     /// Requires https when fetching from remote template repositories
     pub require_https: bool,
+    /// Compression method used for files inside of template files. One of `stored`, `deflate`, `zstd` or `bzip2`
+    pub compression: Compression,
+    /// Compression level passed to the compression method. Uses the default level of the method when not set
+    pub compression_level: Option<i64>,
+    /// File extensions of already compressed files (like png or jar), which are stored without compressing them again
+    pub store_extensions: Vec<String>,
+    /// How new templates are stored. `archive` writes a self contained template file per iteration,
+    /// `blobs` stores file contents once in a content addressed blob store shared by all iterations
+    pub storage: StorageBackend,
+    /// Refuse remote templates that are not signed by a key in `~/.foldr/trusted_keys.json`.
+    /// Templates with an invalid signature are always refused. Git sources can't be signed, so they
+    /// are refused as well
+    pub require_signatures: bool,
+    /// Maximum size in bytes of a downloaded template file. 0 disables the limit
+    pub max_download_size: u64,
+    /// Seconds to wait for a connection to a remote template repository
+    pub connect_timeout: u64,
+    /// Seconds to wait for a remote template repository to respond, and to send the template file
+    pub read_timeout: u64,
+    /// How often a failed download is retried. Only connection errors, timeouts and server errors are retried
+    pub download_retries: u32,
+    /// Milliseconds to wait before the first retry of a download. Doubles after every retry
+    pub retry_backoff_ms: u64,
+    /// Maximum total unpacked size in bytes of a fetched template. 0 disables the limit
+    pub max_template_size: u64,
+    /// Maximum number of files and directories in a fetched template. 0 disables the limit
+    pub max_template_entries: usize,
+    /// Maximum directory nesting depth of paths in a fetched template. 0 disables the limit
+    pub max_template_depth: usize,
+    /// Maximum ratio between the unpacked and compressed size of a single file in a fetched template.
+    /// Only checked for files over 1 MiB. 0 disables the limit
+    pub max_compression_ratio: u64,
+    /// Credentials for remote template endpoints, by host (or `host:port`). Each entry can have a `token`
+    /// sent as bearer token, a `username` and `password` sent as basic auth, and extra `headers`.
+    /// `FOLDR_TOKEN_<HOST>` environment variables take precedence, and `~/.netrc` is used as a fallback
+    pub credentials: BTreeMap<String, HostCredentials>,
+    /// Proxy url used for all network requests, like `http://proxy.example.com:3128`. When not set the
+    /// `HTTPS_PROXY` and `HTTP_PROXY` environment variables are used. Hosts in `NO_PROXY` are never proxied
+    pub proxy: Option<String>,
+    /// Path to a PEM file with CA certificates to trust for https, like a company root certificate.
+    /// They are trusted in addition to the built in root certificates
+    pub ca_bundle: Option<PathBuf>,
+    /// Never make network requests. Remote templates and registry indexes are taken from the download
+    /// cache in `~/.foldr/cache`, and anything that is not cached fails. Same as passing `--offline`
+    pub offline: bool,
+    /// The config file in use. Not a config key, it is set when the config is loaded
+    #[serde(skip)]
+    pub file: PathBuf,
 }
 
 impl Config {
     pub fn default() -> Self {
+        Self::defaults().ensure_created()
+    }
+
+    /// Load the configuration from [`path`], or from the default config file when no path is passed.
+    /// Falls back to the default configuration when there is no config file
+    pub fn load(path: Option<PathBuf>) -> Result<Self> {
+        let path = match path {
+            Some(path) => path.expand(),
+            None => {
+                let default_path = PathBuf::from(globals::FOLDR_CONFIG_FILE).expand();
+                if !default_path.exists() {
+                    return Ok(Self::default());
+                }
+                default_path
+            }
+        };
+        let content = fs::read_to_string(&path).map_err(|_| {
+            error(&format!(
+                "Unable to read config file {}",
+                path.to_string_lossy()
+            ))
+        })?;
+        let mut config: Config = serde_json::from_str(&content).map_err(|e| {
+            error(&format!(
+                "Invalid config file {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        })?;
+        config.file = path;
+        return Ok(config.ensure_created());
+    }
+
+    /// The default configuration, without creating the template directory
+    pub fn defaults() -> Self {
         Self {
             template_dir: PathBuf::from("~/.foldr/templates"),
+            template_dirs: vec![],
             use_cache: true,
             require_https: false,
+            compression: Compression::Deflate,
+            compression_level: None,
+            store_extensions: [
+                "png", "jpg", "jpeg", "gif", "webp", "ico", "mp3", "mp4", "webm", "woff", "woff2",
+                "jar", "war", "zip", "gz", "tgz", "bz2", "xz", "zst", "7z", "rar", "whl", "foldr",
+            ]
+            .iter()
+            .map(|e| e.to_string())
+            .collect(),
+            storage: StorageBackend::Archive,
+            require_signatures: false,
+            max_download_size: 512 * 1024 * 1024,
+            connect_timeout: 10,
+            read_timeout: 300,
+            download_retries: 3,
+            retry_backoff_ms: 500,
+            max_template_size: 2 * 1024 * 1024 * 1024,
+            max_template_entries: 100_000,
+            max_template_depth: 64,
+            max_compression_ratio: 200,
+            credentials: BTreeMap::new(),
+            proxy: None,
+            ca_bundle: None,
+            offline: false,
+            file: PathBuf::from(globals::FOLDR_CONFIG_FILE),
         }
-        .ensure_created()
+    }
+
+    /// All directories templates are looked up in, in order of precedence. The first one is
+    /// template_dir, the only one that is written to
+    pub fn template_stores(&self) -> Vec<PathBuf> {
+        let mut stores = vec![self.template_dir.expand()];
+        for store in &self.template_dirs {
+            let store = store.expand();
+            if !stores.contains(&store) {
+                stores.push(store);
+            }
+        }
+        return stores;
     }
 
     fn ensure_created(self) -> Self {
@@ -27,7 +162,6 @@ impl Config {
         return self;
     }
 }
-
 pub trait ExpandablePathBuf {
     fn expand(&self) -> Self;
 }
@@ -0,0 +1,224 @@
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, Serialize};
+use ureq::{RequestBuilder, http::Uri};
+
+use crate::config::{Config, ExpandablePathBuf};
+
+/// Credentials for a single host, as set in the `credentials` config key
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HostCredentials {
+    /// Sent as a bearer token
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// Sent as basic auth together with the password
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Extra headers sent with every request to the host
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Query parameters that commonly carry a secret, like an access token passed in the url
+const SECRET_QUERY_PARAMETERS: [&str; 10] = [
+    "token",
+    "access_token",
+    "private_token",
+    "api_key",
+    "apikey",
+    "key",
+    "password",
+    "secret",
+    "signature",
+    "sig",
+];
+
+/// Resolves and applies the credentials for remote template endpoints.
+/// Credential values are only ever put in request headers, never in output or errors
+pub struct Credentials;
+
+impl Credentials {
+    /// Find the credentials for the host of [`url`]. The `FOLDR_TOKEN_<HOST>` environment variable is
+    /// checked first, then the `credentials` config key, then `~/.netrc`
+    pub fn for_url(config: &Config, url: &str) -> Option<HostCredentials> {
+        let uri: Uri = url.parse().ok()?;
+        let host = uri.host()?.to_lowercase();
+
+        if let Ok(token) = env::var(Self::token_variable(&host)) {
+            return Some(HostCredentials {
+                token: Some(token),
+                ..Default::default()
+            });
+        }
+        if let Some(port) = uri.port_u16()
+            && let Some(credentials) = config.credentials.get(&format!("{}:{}", host, port))
+        {
+            return Some(credentials.clone());
+        }
+        if let Some(credentials) = config.credentials.get(&host) {
+            return Some(credentials.clone());
+        }
+        return Self::from_netrc(&host);
+    }
+
+    /// Name of the environment variable holding a token for [`host`]. For example
+    /// `FOLDR_TOKEN_EXAMPLE_COM` for example.com
+    pub fn token_variable(host: &str) -> String {
+        let host: String = host
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        return format!("FOLDR_TOKEN_{}", host);
+    }
+
+    /// Add the credentials for the host of [`url`] to a request. Requests carrying custom headers don't
+    /// follow redirects, since unlike the Authorization header they would be sent on to the redirect target
+    pub fn apply<B>(config: &Config, url: &str, request: RequestBuilder<B>) -> RequestBuilder<B> {
+        let Some(credentials) = Self::for_url(config, url) else {
+            return request;
+        };
+        let mut request = request;
+        if let Some(token) = &credentials.token {
+            request = request.header("Authorization", format!("Bearer {}", token));
+        } else if let Some(username) = &credentials.username {
+            let password = credentials.password.clone().unwrap_or_default();
+            request = request.header(
+                "Authorization",
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", username, password))
+                ),
+            );
+        }
+        if credentials.headers.is_empty() {
+            return request;
+        }
+        for (name, value) in &credentials.headers {
+            request = request.header(name, value);
+        }
+        return request
+            .config()
+            .max_redirects(0)
+            .max_redirects_will_error(false)
+            .build();
+    }
+
+    /// Remove any username and password from [`url`], and hide the values of query parameters
+    /// carrying a secret, so it can be shown
+    pub fn redact_url(url: &str) -> String {
+        return Self::clean_url(url, Some("***"));
+    }
+
+    /// Remove any username and password and the query parameters carrying a secret from [`url`], so
+    /// it can be stored. Requests to it get their credentials from the `credentials` config key instead
+    pub fn strip_url(url: &str) -> String {
+        return Self::clean_url(url, None);
+    }
+
+    /// Replace the secrets in [`url`] by [`mask`], or leave them out when there is none
+    fn clean_url(url: &str, mask: Option<&str>) -> String {
+        let Some((scheme, rest)) = url.split_once("://") else {
+            return url.to_string();
+        };
+        let authority_end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+        let mut cleaned = format!("{}://", scheme);
+        let rest = match rest[..authority_end].rfind('@') {
+            Some(at) => {
+                if let Some(mask) = mask {
+                    cleaned.push_str(&format!("{}@", mask));
+                }
+                &rest[at + 1..]
+            }
+            None => rest,
+        };
+        let (rest, fragment) = match rest.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment)),
+            None => (rest, None),
+        };
+        let Some((path, query)) = rest.split_once('?') else {
+            cleaned.push_str(rest);
+            if let Some(fragment) = fragment {
+                cleaned.push_str(&format!("#{}", fragment));
+            }
+            return cleaned;
+        };
+        let parameters: Vec<String> = query
+            .split('&')
+            .filter_map(|parameter| {
+                let name = parameter.split('=').next().unwrap_or_default();
+                if !SECRET_QUERY_PARAMETERS.contains(&name.to_lowercase().as_str()) {
+                    return Some(parameter.to_string());
+                }
+                return mask.map(|mask| format!("{}={}", name, mask));
+            })
+            .collect();
+        cleaned.push_str(path);
+        if !parameters.is_empty() {
+            cleaned.push_str(&format!("?{}", parameters.join("&")));
+        }
+        if let Some(fragment) = fragment {
+            cleaned.push_str(&format!("#{}", fragment));
+        }
+        return cleaned;
+    }
+
+    fn from_netrc(host: &str) -> Option<HostCredentials> {
+        let path = env::var("NETRC")
+            .map(PathBuf::from)
+            .unwrap_or(PathBuf::from("~/.netrc"))
+            .expand();
+        let content = fs::read_to_string(path).ok()?;
+        let mut tokens = content.split_whitespace();
+        let mut matched: Option<HostCredentials> = None;
+        let mut default: Option<HostCredentials> = None;
+        // Which entry the login and password tokens currently belong to
+        let mut current: Option<&mut HostCredentials> = None;
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" => {
+                    let is_host = tokens.next().is_some_and(|m| m.eq_ignore_ascii_case(host));
+                    current = None;
+                    if is_host && matched.is_none() {
+                        matched = Some(HostCredentials::default());
+                        current = matched.as_mut();
+                    }
+                }
+                "default" => {
+                    default = Some(HostCredentials::default());
+                    current = default.as_mut();
+                }
+                "login" => {
+                    let value = tokens.next().map(String::from);
+                    if let Some(entry) = current.as_deref_mut() {
+                        entry.username = value;
+                    }
+                }
+                "password" => {
+                    let value = tokens.next().map(String::from);
+                    if let Some(entry) = current.as_deref_mut() {
+                        entry.password = value;
+                    }
+                }
+                "account" => {
+                    tokens.next();
+                }
+                // Macro definitions are free form text, and come after the machine entries
+                "macdef" => break,
+                _ => {}
+            }
+        }
+        return matched
+            .or(default)
+            .filter(|credentials| credentials.username.is_some());
+    }
+}
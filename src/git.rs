@@ -0,0 +1,325 @@
+use std::{
+    collections::BTreeSet,
+    ffi::OsStr,
+    fs::{self, File},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+
+use tempfile::{NamedTempFile, TempDir};
+
+use crate::{
+    commands::command::{Iteration, Result, error},
+    config::Config,
+    credentials::Credentials,
+    globals::FOLDR_MANIFEST_FILE,
+    templates::{SpawnTarget, Template, TemplateInfo},
+    zip::{ArchiveOptions, Compression, ZipUtil},
+};
+
+/// Templates stored in git repositories, referred to as `git+<url>#<ref>:<subdirectory>`.
+/// Both the ref and the subdirectory are optional
+pub struct GitSource {
+    /// Url of the repository, without the git+ prefix
+    pub url: String,
+    /// Branch, tag or commit to check out. Defaults to the default branch of the repository
+    pub reference: Option<String>,
+    /// Directory inside the repository to use as the template. Defaults to the whole repository
+    pub subdirectory: Option<PathBuf>,
+}
+
+impl GitSource {
+    pub fn is_git_source(endpoint: &str) -> bool {
+        return endpoint.starts_with("git+");
+    }
+
+    pub fn parse(config: &Config, endpoint: &str) -> Result<GitSource> {
+        let Some(source) = endpoint.strip_prefix("git+") else {
+            return Err(error("Git sources must start with git+"));
+        };
+        let (url, fragment) = match source.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (source, None),
+        };
+        if !url.starts_with("https://")
+            && !url.starts_with("http://")
+            && !url.starts_with("file://")
+        {
+            return Err(error(
+                "Git sources must be git+https:// or git+file:// urls",
+            ));
+        }
+        if config.require_https && url.starts_with("http://") {
+            return Err(error("Non https endpoints not allowed by config"));
+        }
+        // Signatures are published next to template files, a git repository has no place for them
+        if config.require_signatures {
+            return Err(error(
+                "Git sources can't be signed, and unsigned templates are not allowed by config",
+            ));
+        }
+        if config.offline && !url.starts_with("file://") {
+            return Err(error(
+                "Remote git repositories can't be cloned while offline",
+            ));
+        }
+        let (reference, subdirectory) = match fragment.map(|f| f.split_once(':').unwrap_or((f, "")))
+        {
+            Some((reference, subdirectory)) => (
+                Some(reference).filter(|r| !r.is_empty()),
+                Some(subdirectory).filter(|s| !s.is_empty()),
+            ),
+            None => (None, None),
+        };
+        if reference.is_some_and(|r| r.starts_with('-')) {
+            return Err(error("Invalid git ref"));
+        }
+        let subdirectory = subdirectory.map(PathBuf::from);
+        if subdirectory.as_ref().is_some_and(|s| {
+            s.components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+        }) {
+            return Err(error(
+                "The subdirectory of a git source must be a relative path inside the repository",
+            ));
+        }
+        return Ok(GitSource {
+            url: url.into(),
+            reference: reference.map(String::from),
+            subdirectory,
+        });
+    }
+
+    /// Clone the repository into a temporary directory and check out the ref. Returns the directory
+    /// and the path of the template directory inside it. The clone is removed when the directory is dropped
+    pub fn checkout(&self) -> Result<(TempDir, PathBuf)> {
+        let clone =
+            tempfile::tempdir().map_err(|_| error("IO error creating temporary directory"))?;
+        eprintln!("Cloning {}", Credentials::redact_url(&self.url));
+        let mut arguments = vec!["clone", "--quiet"];
+        // A shallow clone only has the commit at the tip of the default branch
+        if self.reference.is_some() {
+            arguments.push("--no-checkout");
+        } else {
+            arguments.push("--depth=1");
+        }
+        self.git(
+            None,
+            &arguments,
+            &[self.url.as_ref(), clone.path().as_os_str()],
+        )?;
+        if let Some(reference) = &self.reference {
+            self.git(
+                Some(clone.path()),
+                &["checkout", "--quiet", reference, "--"],
+                &[],
+            )?;
+        }
+        let commit = self.git(Some(clone.path()), &["rev-parse", "HEAD"], &[])?;
+        eprintln!("Checked out commit {}", commit);
+        fs::remove_dir_all(clone.path().join(".git"))
+            .map_err(|_| error("IO error removing .git directory from clone"))?;
+
+        let directory = match &self.subdirectory {
+            Some(subdirectory) => clone.path().join(subdirectory),
+            None => clone.path().to_path_buf(),
+        };
+        // Symlinks in the repository could point the subdirectory anywhere on disk
+        let inside_clone = fs::canonicalize(&directory).is_ok_and(|resolved| {
+            fs::canonicalize(clone.path()).is_ok_and(|root| resolved.starts_with(root))
+        });
+        if !directory.is_dir() || !inside_clone {
+            return Err(error(&format!(
+                "Directory {} does not exist in the repository",
+                self.subdirectory.as_ref().unwrap().to_string_lossy()
+            )));
+        }
+        return Ok((clone, directory));
+    }
+
+    fn git(
+        &self,
+        directory: Option<&Path>,
+        arguments: &[&str],
+        paths: &[&OsStr],
+    ) -> Result<String> {
+        let output = run_git(directory, arguments, paths, Some(&self.url))?;
+        return Ok(String::from_utf8_lossy(&output).trim().to_string());
+    }
+
+    /// Clone a git source and store it as [`iteration`] of the template [`name`]
+    pub fn fetch_template(
+        &self,
+        config: &Config,
+        name: &str,
+        iteration: Iteration,
+    ) -> Result<Template> {
+        let (_clone, directory) = self.checkout()?;
+        return Template::save(
+            config,
+            &directory,
+            name,
+            iteration,
+            &ArchiveOptions::from_config(config),
+        );
+    }
+
+    /// Clone a git source and spawn it at [`target`] without storing it
+    pub fn fetch_and_spawn_template(&self, config: &Config, target: &SpawnTarget) -> Result<()> {
+        let (_clone, directory) = self.checkout()?;
+        let archive =
+            NamedTempFile::new().map_err(|_| error("IO error creating temporary archive"))?;
+        let mut options = ArchiveOptions::from_config(config);
+        options.compression = Compression::Stored;
+        let mut info = TemplateInfo::new("git".into(), 1);
+        ZipUtil::zip_dir(
+            &directory,
+            &archive.path().to_path_buf(),
+            &mut info,
+            &options,
+        )?;
+        let file = File::open(archive.path()).map_err(|_| error("IO error opening archive"))?;
+        return Template::spawn_from_stream(config, target, file, vec![FOLDR_MANIFEST_FILE.into()]);
+    }
+}
+
+/// The commit a template was saved from with --git-tracked, recorded in its manifest
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GitProvenance {
+    pub commit: String,
+    /// Url of the origin remote, without credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Whether tracked files had uncommitted changes, which are part of the template
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub modified: bool,
+}
+
+/// The files git tracks in a directory of a repository working tree
+pub struct GitTree;
+
+impl GitTree {
+    /// Get the paths of the files git tracks in [`directory`], relative to it, together with the
+    /// commit checked out. Tracked files that were deleted from the working tree are left out
+    pub fn tracked_files(directory: &Path) -> Result<(BTreeSet<PathBuf>, GitProvenance)> {
+        let output = run_git(Some(directory), &["ls-files", "-z", "--cached"], &[], None)?;
+        let files = output
+            .split(|b| *b == 0)
+            .filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(String::from_utf8_lossy(path).into_owned()))
+            .filter(|path| fs::symlink_metadata(directory.join(path)).is_ok())
+            .collect();
+        let status = run_git(
+            Some(directory),
+            &["status", "--porcelain", "--untracked-files=no"],
+            &[OsStr::new(".")],
+            None,
+        )?;
+        let provenance = GitProvenance {
+            modified: !status.is_empty(),
+            ..Self::provenance(directory, "HEAD")?
+        };
+        return Ok((files, provenance));
+    }
+
+    /// Write the files git tracks in [`directory`] at [`reference`] into a temporary directory, which
+    /// is removed when it is dropped
+    pub fn export(directory: &Path, reference: &str) -> Result<(TempDir, GitProvenance)> {
+        if reference.starts_with('-') {
+            return Err(error("Invalid git ref"));
+        }
+        let output = run_git(
+            Some(directory),
+            &["rev-parse", "--show-toplevel", "--show-prefix"],
+            &[],
+            None,
+        )?;
+        let output = String::from_utf8_lossy(&output);
+        let mut lines = output.lines();
+        let toplevel = lines.next().unwrap_or_default();
+        let prefix = lines.next().unwrap_or_default();
+        let provenance = Self::provenance(directory, reference)?;
+        // git archive only includes the current directory, so it runs at the top level with the tree
+        // of the directory
+        let tree = format!("{}:{}", provenance.commit, prefix);
+        let export =
+            tempfile::tempdir().map_err(|_| error("IO error creating temporary directory"))?;
+        let mut child = Command::new("git")
+            .current_dir(toplevel)
+            .args(["archive", "--format=tar", &tree])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|_| error("git is required for --git-tracked, but it could not be run"))?;
+        let unpacked = tar::Archive::new(child.stdout.take().unwrap()).unpack(export.path());
+        let status = child.wait().map_err(|_| error("git archive failed"))?;
+        if !status.success() || unpacked.is_err() {
+            return Err(error(&format!(
+                "Unable to read the tracked files at {}",
+                reference
+            )));
+        }
+        return Ok((export, provenance));
+    }
+
+    fn provenance(directory: &Path, reference: &str) -> Result<GitProvenance> {
+        let commit = run_git(
+            Some(directory),
+            &[
+                "rev-parse",
+                "--verify",
+                "--end-of-options",
+                &format!("{}^{{commit}}", reference),
+            ],
+            &[],
+            None,
+        )
+        .map_err(|_| {
+            error(&format!(
+                "{} is not a commit in the git repository",
+                reference
+            ))
+        })?;
+        let remote = run_git(Some(directory), &["remote", "get-url", "origin"], &[], None)
+            .ok()
+            .map(|remote| Credentials::redact_url(String::from_utf8_lossy(&remote).trim()))
+            .filter(|remote| !remote.is_empty());
+        return Ok(GitProvenance {
+            commit: String::from_utf8_lossy(&commit).trim().to_string(),
+            remote,
+            modified: false,
+        });
+    }
+}
+
+/// Run git with [`arguments`], followed by `--` and [`paths`]. Returns what git printed.
+/// Prompts are disabled, so credentials must come from a git credential helper. [`url`] is
+/// shown without credentials when git includes it in an error
+fn run_git(
+    directory: Option<&Path>,
+    arguments: &[&str],
+    paths: &[&OsStr],
+    url: Option<&str>,
+) -> Result<Vec<u8>> {
+    let mut command = Command::new("git");
+    if let Some(directory) = directory {
+        command.current_dir(directory);
+    }
+    command.args(arguments).env("GIT_TERMINAL_PROMPT", "0");
+    if !paths.is_empty() {
+        command.arg("--").args(paths);
+    }
+    let output = command
+        .output()
+        .map_err(|_| error("git is required for git sources, but it could not be run"))?;
+    if !output.status.success() {
+        let mut message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if let Some(url) = url {
+            message = message.replace(url, &Credentials::redact_url(url));
+        }
+        return Err(error(&format!("git {} failed: {}", arguments[0], message)));
+    }
+    return Ok(output.stdout);
+}
@@ -2,3 +2,15 @@ pub const FOLDR_MANIFEST_FILE: &'static str = ".foldrmanifest.json";
 pub const FOLDR_TEMPLATE_DIR: &'static str = "~/.foldr/templates";
 pub const FOLDR_CONFIG_DIR: &'static str = "~/.foldr/";
 pub const FOLDR_CONFIG_FILE: &'static str = "~/.foldr/config.json";
+pub const FOLDR_TEMPLATE_EXTENSION: &'static str = "foldr";
+pub const FOLDR_TREE_EXTENSION: &'static str = "foldrtree";
+pub const FOLDR_BLOB_DIR: &'static str = "blobs";
+pub const FOLDR_SIGNATURE_EXTENSION: &'static str = "sig";
+pub const FOLDR_KEYS_DIR: &'static str = "~/.foldr/keys";
+pub const FOLDR_TRUSTED_KEYS_FILE: &'static str = "~/.foldr/trusted_keys.json";
+pub const FOLDR_REGISTRIES_FILE: &'static str = "~/.foldr/registries.json";
+pub const FOLDR_SOURCE_EXTENSION: &'static str = "source.json";
+pub const FOLDR_CACHE_DIR: &'static str = "~/.foldr/cache";
+pub const FOLDR_PACK_INDEX_FILE: &'static str = "index.json";
+pub const FOLDR_BACKUP_INFO_FILE: &'static str = "backup.json";
+pub const FOLDR_STORE_MOVE_FILE: &'static str = "~/.foldr/store-move.json";
@@ -3,11 +3,23 @@ use std::process::ExitCode;
 use clap::{CommandFactory, Parser};
 use commands::command::run;
 
+mod archives;
+mod backups;
+mod blobs;
+mod cache;
 mod cli;
 mod commands;
 mod config;
+mod credentials;
+mod git;
 mod globals;
 mod network;
+mod packs;
+mod registry;
+mod server;
+mod signing;
+mod sources;
+mod store;
 mod templates;
 mod zip;
 
@@ -23,8 +35,18 @@ fn main() -> ExitCode {
     }
 
     let command = command.unwrap();
-    if let Err(err) = run(command, config::Config::default()) {
-        println!("Something went wrong during the operation: {}", err.message);
+    let mut config = match config::Config::load(cli.config_path) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Unable to load configuration: {}", err.message);
+            return ExitCode::FAILURE;
+        }
+    };
+    if cli.offline {
+        config.offline = true;
+    }
+    if let Err(err) = run(command, config) {
+        eprintln!("Something went wrong during the operation: {}", err.message);
         return ExitCode::FAILURE;
     }
 
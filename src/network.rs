@@ -1,59 +1,311 @@
-use std::io::Cursor;
+use std::io::{BufReader, Read, Seek, SeekFrom};
 use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use std::{env, fs, fs::File};
 
-use crate::commands::command::{Iteration, Result, error};
-use crate::config::Config;
-use crate::globals::FOLDR_MANIFEST_FILE;
-use crate::templates::Template;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::de::DeserializeOwned;
+use tempfile::NamedTempFile;
+use ureq::http::{StatusCode, Uri};
+use ureq::tls::{Certificate, PemItem, RootCerts, TlsConfig, parse_pem};
+use ureq::typestate::WithoutBody;
+use ureq::{Agent, AsSendBody, Proxy, RequestBuilder};
+
+use crate::archives::ForeignArchive;
+use crate::blobs::HashingWriter;
+use crate::cache::{CacheEntry, DownloadCache};
+use crate::commands::command::{CommandError, Iteration, Result, error};
+use crate::config::{Config, ExpandablePathBuf};
+use crate::credentials::Credentials;
+use crate::globals::{FOLDR_MANIFEST_FILE, FOLDR_SIGNATURE_EXTENSION};
+use crate::signing::{Signing, TemplateSignature};
+use crate::sources::TemplateSource;
+use crate::templates::{SpawnTarget, Template};
+use crate::zip::ArchiveLimits;
 pub struct NetworkUtil;
 
+/// A failed download attempt, and whether trying again could succeed
+struct FailedAttempt {
+    error: CommandError,
+    retry: bool,
+}
+
+/// A downloaded file, with the sha256 of its contents and the validators the server sent for it
+struct Download {
+    file: File,
+    sha256: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// Digest of the template contents, once the download was checked
+    digest: Option<String>,
+}
+
+impl Download {
+    fn from_cache(entry: CacheEntry) -> Result<Self> {
+        return Ok(Self {
+            file: entry.open()?,
+            sha256: entry.source.sha256,
+            etag: entry.source.etag,
+            last_modified: entry.source.last_modified,
+            digest: None,
+        });
+    }
+    /// Where the download came from. Secrets in the url are left out, they only live in the
+    /// `credentials` config key
+    fn source(&self, url: &str) -> TemplateSource {
+        return TemplateSource {
+            url: Credentials::strip_url(url),
+            reference: None,
+            etag: self.etag.clone(),
+            last_modified: self.last_modified.clone(),
+            sha256: self.sha256.clone(),
+            strip_top_level: false,
+        };
+    }
+}
+
 impl NetworkUtil {
+    /// Download a template and store it as [`iteration`] of [`name`]. [`reference`] is the registry
+    /// reference the endpoint was resolved from, which is recorded so refreshing checks the registry again
     pub fn fetch_template(
         config: &Config,
         endpoint: String,
         name: String,
         iteration: Iteration,
+        sha256: Option<&str>,
+        strip_top_level: bool,
+        reference: Option<String>,
     ) -> Result<Template> {
-        if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
-            return Err(error("Endpoint passed is not an http(s) endpoint"));
+        let (download, signature) =
+            Self::download_template(config, &endpoint, sha256, strip_top_level, None)?
+                .ok_or_else(Self::unexpected_not_modified)?;
+        let source = TemplateSource {
+            reference,
+            strip_top_level,
+            ..download.source(&endpoint)
+        };
+        if source.url != endpoint {
+            eprintln!(
+                "Credentials in the url are not stored with the template. Add them to the credentials config key to refresh it"
+            );
         }
-
-        if config.require_https && !endpoint.starts_with("https") {
-            return Err(error("Non https endpoints not allowed by config"));
-        }
-        println!("Fetching Template From {}", endpoint.to_string());
-        let mut response = ureq::get(endpoint).call().map_err(|e| {
-            error(&format!(
-                "Network error while fetching template over http: {}",
-                e.to_string()
-            ))
-        })?;
-
-        if !response.status().is_success() {
-            return Err(error(&format!(
-                "Failed statuscode recieved from endpoint: {}",
-                response.status().as_str()
-            )));
+        return Self::store_download(config, name, iteration, download, signature, source);
+    }
+    /// Download a template again from where [`previous`] was fetched from, sending the validators of the
+    /// previous download so the server can skip sending an unchanged file. Stores the next iteration
+    /// only when the contents changed, and returns None when they did not
+    pub fn refresh_template(
+        config: &Config,
+        previous: &Template,
+        source: &TemplateSource,
+        endpoint: String,
+        sha256: Option<&str>,
+    ) -> Result<Option<Template>> {
+        // Validators only apply to the url they were sent for, a registry may point somewhere else now
+        let conditions = if Credentials::strip_url(&endpoint) == source.url {
+            Some(source)
+        } else {
+            None
+        };
+        let Some((download, signature)) = Self::download_template(
+            config,
+            &endpoint,
+            sha256,
+            source.strip_top_level,
+            conditions,
+        )?
+        else {
+            return Ok(None);
+        };
+        let source = TemplateSource {
+            reference: source.reference.clone(),
+            strip_top_level: source.strip_top_level,
+            ..download.source(&endpoint)
+        };
+        return Self::store_refresh(config, previous, download, signature, source);
+    }
+    /// Store a download as the next iteration of [`previous`], unless its contents are the same
+    fn store_refresh(
+        config: &Config,
+        previous: &Template,
+        download: Download,
+        signature: Option<TemplateSignature>,
+        source: TemplateSource,
+    ) -> Result<Option<Template>> {
+        // The same contents in a different file, like a template file a server builds per download
+        if download.digest.is_some() && download.digest == previous.info.digest {
+            // Keep the new validators, so the next refresh can be conditional on them
+            if previous.ensure_writable(config).is_ok() {
+                source.write(previous)?;
+            }
+            return Ok(None);
         }
-        let mut buffer = response
-            .body_mut()
-            .read_to_vec()
-            .map_err(|_| error("Error while reading template http stream"))?;
-        let mut cursor = Cursor::new(&mut buffer);
+        return Self::store_download(
+            config,
+            previous.info.name.clone(),
+            previous.info.iteration + 1,
+            download,
+            signature,
+            source,
+        )
+        .map(Some);
+    }
+    fn store_download(
+        config: &Config,
+        name: String,
+        iteration: Iteration,
+        mut download: Download,
+        signature: Option<TemplateSignature>,
+        source: TemplateSource,
+    ) -> Result<Template> {
         let template = Template::store(
-            &config,
+            config,
             name,
             iteration,
-            &mut cursor,
+            &mut download.file,
             vec![FOLDR_MANIFEST_FILE.into()],
         )?;
+        if let Some(signature) = signature {
+            Signing::write_signature(&template, &signature)?;
+        }
+        source.write(&template)?;
         return Ok(template);
     }
     pub fn fetch_and_spawn_template(
         config: &Config,
         endpoint: String,
-        spawn_path: PathBuf,
+        target: &SpawnTarget,
+        sha256: Option<&str>,
+        strip_top_level: bool,
     ) -> Result<()> {
+        let (mut download, _) =
+            Self::download_template(config, &endpoint, sha256, strip_top_level, None)?
+                .ok_or_else(Self::unexpected_not_modified)?;
+        Template::spawn_from_stream(
+            config,
+            target,
+            &mut download.file,
+            vec![FOLDR_MANIFEST_FILE.into()],
+        )?;
+        return Ok(());
+    }
+    /// Download a template file and its signature. Checks the downloaded bytes against [`sha256`] when
+    /// passed, and the signature against the downloaded contents.
+    /// When [`previous`] is passed the request is conditional, and None is returned when the template
+    /// did not change since then
+    fn download_template(
+        config: &Config,
+        endpoint: &str,
+        sha256: Option<&str>,
+        strip_top_level: bool,
+        previous: Option<&TemplateSource>,
+    ) -> Result<Option<(Download, Option<TemplateSignature>)>> {
+        Self::check_endpoint(config, endpoint)?;
+        let agent = Self::agent(config)?;
+        if !config.offline {
+            eprintln!(
+                "Fetching Template From {}",
+                Credentials::redact_url(endpoint)
+            );
+        }
+        let Some(mut download) = Self::download_cached(config, &agent, endpoint, previous)? else {
+            return Ok(None);
+        };
+        // Servers that don't support conditional requests send the whole file again
+        if previous.is_some_and(|previous| previous.sha256 == download.sha256) {
+            return Ok(None);
+        }
+        match sha256 {
+            Some(expected) if !expected.trim().eq_ignore_ascii_case(&download.sha256) => {
+                return Err(error(&format!(
+                    "Checksum mismatch for downloaded template\nExpected: {}\nActual:   {}",
+                    expected.trim(),
+                    download.sha256
+                )));
+            }
+            Some(_) => eprintln!("Checksum verified"),
+            None => eprintln!("Sha256: {}", download.sha256),
+        }
+        let signature = if config.offline {
+            DownloadCache::get(endpoint)?.and_then(|entry| entry.signature)
+        } else {
+            Self::fetch_signature(config, &agent, endpoint)?
+        };
+        let converted = ForeignArchive::to_template_file(config, &download.file, strip_top_level)?;
+        let digest = Signing::archive_digest(
+            converted.as_ref().unwrap_or(&download.file),
+            &ArchiveLimits::from_config(config),
+        )?;
+        Signing::check_download(config, signature.as_ref(), &digest)?;
+        download.digest = Some(digest);
+        if !config.offline {
+            // The cache holds the file as downloaded, so its hash still matches
+            DownloadCache::store(
+                endpoint,
+                &download.file,
+                &download.source(endpoint),
+                signature.as_ref(),
+            )?;
+        }
+        if let Some(converted) = converted {
+            download.file = converted;
+        }
+        Self::rewind(&download.file)?;
+        return Ok(Some((download, signature)));
+    }
+    /// Download [`url`] through the download cache. When offline only the cache is used. Otherwise the
+    /// request is made conditional on the cached copy, which is used when the server reports it is
+    /// still current. When [`previous`] is passed the request is conditional on it instead, and None
+    /// is returned when the server reports it is still current
+    fn download_cached(
+        config: &Config,
+        agent: &Agent,
+        url: &str,
+        previous: Option<&TemplateSource>,
+    ) -> Result<Option<Download>> {
+        let cached = DownloadCache::get(url)?;
+        if config.offline {
+            let Some(cached) = cached else {
+                return Err(DownloadCache::not_cached(url));
+            };
+            eprintln!("Using cached copy of {}", Credentials::redact_url(url));
+            return Ok(Some(Download::from_cache(cached)?));
+        }
+        let validators = previous.or(cached.as_ref().map(|entry| &entry.source));
+        return match Self::download(config, agent, url, validators)? {
+            Some(download) => Ok(Some(download)),
+            None if previous.is_some() => Ok(None),
+            None => {
+                let cached = cached.ok_or_else(Self::unexpected_not_modified)?;
+                eprintln!("Not modified since it was cached, using cached copy");
+                Ok(Some(Download::from_cache(cached)?))
+            }
+        };
+    }
+    fn unexpected_not_modified() -> CommandError {
+        return error("Server responded not modified to a request that was not conditional");
+    }
+    /// Download a json document, like a registry index
+    pub fn fetch_json<T: DeserializeOwned>(config: &Config, url: &str) -> Result<T> {
+        Self::check_endpoint(config, url)?;
+        let agent = Self::agent(config)?;
+        let download = Self::download_cached(config, &agent, url, None)?
+            .ok_or_else(Self::unexpected_not_modified)?;
+        if !config.offline {
+            DownloadCache::store(url, &download.file, &download.source(url), None)?;
+        }
+        Self::rewind(&download.file)?;
+        return serde_json::from_reader(BufReader::new(&download.file)).map_err(|e| {
+            error(&format!(
+                "Invalid json received from {}: {}",
+                Credentials::redact_url(url),
+                e
+            ))
+        });
+    }
+    /// Check that [`endpoint`] is an http(s) url, and that it is https when the config requires it
+    pub fn check_endpoint(config: &Config, endpoint: &str) -> Result<()> {
         if !endpoint.starts_with("http://") && !endpoint.starts_with("https://") {
             return Err(error("Endpoint passed is not an http(s) endpoint"));
         }
@@ -61,31 +313,355 @@ impl NetworkUtil {
         if config.require_https && !endpoint.starts_with("https") {
             return Err(error("Non https endpoints not allowed by config"));
         }
-        println!("Fetching Template From {}", endpoint.to_string());
-        let mut response = ureq::get(endpoint).call().map_err(|e| {
+        return Ok(());
+    }
+    /// Build the http agent used for all requests, with the timeouts, proxy and CA bundle from the config
+    fn agent(config: &Config) -> Result<Agent> {
+        let read_timeout = Some(Duration::from_secs(config.read_timeout));
+        let proxy = match &config.proxy {
+            Some(proxy) => Some(Proxy::new(proxy).map_err(|_| {
+                error(&format!(
+                    "Invalid proxy in config: {}",
+                    Credentials::redact_url(proxy)
+                ))
+            })?),
+            None => Proxy::try_from_env(),
+        };
+        let mut builder = Agent::config_builder()
+            .timeout_connect(Some(Duration::from_secs(config.connect_timeout)))
+            .timeout_recv_response(read_timeout)
+            .timeout_recv_body(read_timeout)
+            .proxy(proxy);
+        if let Some(ca_bundle) = &config.ca_bundle {
+            // The bundle is trusted in addition to the built in Mozilla root certificates
+            let mut certificates: Vec<Certificate<'static>> =
+                webpki_root_certs::TLS_SERVER_ROOT_CERTS
+                    .iter()
+                    .map(|certificate| Certificate::from_der(certificate.as_ref()))
+                    .collect();
+            certificates.extend(Self::load_certificates(&ca_bundle.expand())?);
+            builder = builder.tls_config(
+                TlsConfig::builder()
+                    .root_certs(RootCerts::new_with_certs(&certificates))
+                    .build(),
+            );
+        }
+        return Ok(builder.build().into());
+    }
+    fn load_certificates(path: &PathBuf) -> Result<Vec<Certificate<'static>>> {
+        let content = fs::read(path).map_err(|_| {
             error(&format!(
-                "Network error while fetching template over http: {}",
-                e.to_string()
+                "Unable to read CA bundle {}",
+                path.to_string_lossy()
             ))
         })?;
+        let mut certificates = vec![];
+        for item in parse_pem(&content) {
+            match item {
+                Ok(PemItem::Certificate(certificate)) => certificates.push(certificate),
+                Ok(_) => {}
+                Err(_) => {
+                    return Err(error(&format!(
+                        "CA bundle {} is not a valid PEM file",
+                        path.to_string_lossy()
+                    )));
+                }
+            }
+        }
+        if certificates.is_empty() {
+            return Err(error(&format!(
+                "CA bundle {} contains no certificates",
+                path.to_string_lossy()
+            )));
+        }
+        return Ok(certificates);
+    }
+    /// Create a GET request for [`url`]
+    fn get(config: &Config, agent: &Agent, url: &str) -> RequestBuilder<WithoutBody> {
+        return Self::prepare(config, url, agent.get(url));
+    }
+    /// Add the credentials for the host of [`url`] to a request. Hosts listed in the
+    /// `NO_PROXY` environment variable are connected to directly
+    fn prepare<B>(config: &Config, url: &str, request: RequestBuilder<B>) -> RequestBuilder<B> {
+        let mut request = request;
+        if Self::bypass_proxy(url) {
+            request = request.config().proxy(None).build();
+        }
+        return Credentials::apply(config, url, request);
+    }
+    /// Upload [`body`] to [`url`] with a PUT request. Returns the status code and the text the server
+    /// responded with, also when the status is not a success
+    pub fn upload<B: AsSendBody>(config: &Config, url: &str, body: B) -> Result<(u16, String)> {
+        Self::check_endpoint(config, url)?;
+        let agent = Self::agent(config)?;
+        let request = Self::prepare(config, url, agent.put(url))
+            .config()
+            .http_status_as_error(false)
+            .build();
+        let mut response = request.send(body).map_err(|e| {
+            error(&format!(
+                "Network error while uploading over http: {}",
+                Self::redact_error(&e, url)
+            ))
+        })?;
+        let status = response.status().as_u16();
+        let message = response.body_mut().read_to_string().unwrap_or_default();
+        return Ok((status, message.trim().to_string()));
+    }
+    fn bypass_proxy(url: &str) -> bool {
+        let Some(no_proxy) = env::var("NO_PROXY").or_else(|_| env::var("no_proxy")).ok() else {
+            return false;
+        };
+        let Some(host) = url
+            .parse::<Uri>()
+            .ok()
+            .and_then(|uri| uri.host().map(|h| h.to_lowercase()))
+        else {
+            return false;
+        };
+        return no_proxy
+            .split(',')
+            .map(|entry| entry.trim().trim_start_matches('.').to_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)));
+    }
+    /// Download [`endpoint`] into a temporary file, retrying failures that might be temporary.
+    /// Returns the file rewound to the start, or None when the request was made conditional on
+    /// [`previous`] and the server reports it is not modified.
+    /// The temporary file is removed when it is dropped, so failed downloads leave nothing behind
+    fn download(
+        config: &Config,
+        agent: &Agent,
+        endpoint: &str,
+        previous: Option<&TemplateSource>,
+    ) -> Result<Option<Download>> {
+        let mut attempt = 0;
+        loop {
+            match Self::try_download(config, agent, endpoint, previous) {
+                Ok(download) => return Ok(download),
+                Err(failed) if failed.retry && attempt < config.download_retries => {
+                    let backoff = config.retry_backoff_ms.saturating_mul(1 << attempt.min(16));
+                    attempt += 1;
+                    eprintln!(
+                        "{}. Retrying in {} ms ({}/{})",
+                        failed.error.message, backoff, attempt, config.download_retries
+                    );
+                    thread::sleep(Duration::from_millis(backoff));
+                }
+                Err(failed) => return Err(failed.error),
+            }
+        }
+    }
+    fn try_download(
+        config: &Config,
+        agent: &Agent,
+        endpoint: &str,
+        previous: Option<&TemplateSource>,
+    ) -> std::result::Result<Option<Download>, FailedAttempt> {
+        let mut request = Self::get(config, agent, endpoint);
+        if let Some(previous) = previous {
+            if let Some(etag) = &previous.etag {
+                request = request.header("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &previous.last_modified {
+                request = request.header("If-Modified-Since", last_modified);
+            }
+        }
+        let mut response = request.call().map_err(|e| FailedAttempt {
+            retry: match &e {
+                ureq::Error::StatusCode(status) => *status >= 500 || *status == 429,
+                _ => true,
+            },
+            error: error(&format!(
+                "Network error while fetching template over http: {}",
+                Self::redact_error(&e, endpoint)
+            )),
+        })?;
+        let fatal = |message: &str| FailedAttempt {
+            error: error(message),
+            retry: false,
+        };
 
+        if previous.is_some() && response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
         if !response.status().is_success() {
-            return Err(error(&format!(
+            return Err(fatal(&format!(
                 "Failed statuscode recieved from endpoint: {}",
                 response.status().as_str()
             )));
         }
-        let mut buffer = response
-            .body_mut()
-            .read_to_vec()
-            .map_err(|_| error("Error while reading template http stream"))?;
-        let mut cursor = Cursor::new(&mut buffer);
-        let template = Template::spawn_from_stream(
+        let limit = config.max_download_size;
+        let length = response.body().content_length();
+        if limit > 0
+            && let Some(length) = length
+            && length > limit
+        {
+            return Err(fatal(&format!(
+                "Template is {}, which is larger than the maximum download size of {}",
+                bytesize::ByteSize::b(length),
+                bytesize::ByteSize::b(limit)
+            )));
+        }
+
+        let download =
+            NamedTempFile::new().map_err(|_| fatal("IO error creating temporary download file"))?;
+        let bar = match length {
+            Some(length) => ProgressBar::new(length).with_style(
+                ProgressStyle::with_template(
+                    "{bar:40} {binary_bytes}/{binary_total_bytes} ({binary_bytes_per_sec})",
+                )
+                .unwrap(),
+            ),
+            None => ProgressBar::new_spinner(),
+        };
+        let mut writer = HashingWriter::new(bar.wrap_write(download.as_file()));
+        let mut reader = response.body_mut().as_reader();
+        let copied = if limit > 0 {
+            // Read one byte past the limit to tell a body that is exactly at the limit from one that's over it
+            std::io::copy(&mut (&mut reader).take(limit + 1), &mut writer)
+        } else {
+            std::io::copy(&mut reader, &mut writer)
+        };
+        bar.finish_and_clear();
+        copied.map_err(|e| FailedAttempt {
+            error: error(&format!("Error while reading template http stream: {}", e)),
+            retry: true,
+        })?;
+        if limit > 0 && writer.written > limit {
+            return Err(fatal(&format!(
+                "Template is larger than the maximum download size of {}",
+                bytesize::ByteSize::b(limit)
+            )));
+        }
+        let sha256 = writer.hash();
+        Self::rewind(download.as_file()).map_err(|error| FailedAttempt {
+            error,
+            retry: false,
+        })?;
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        return Ok(Some(Download {
+            // The file stays readable through the handle, while its path is removed right away
+            file: download.into_file(),
+            sha256,
+            etag: header("ETag"),
+            last_modified: header("Last-Modified"),
+            digest: None,
+        }));
+    }
+    /// Format a request error without any credentials that are part of the url
+    fn redact_error(e: &ureq::Error, endpoint: &str) -> String {
+        return e
+            .to_string()
+            .replace(endpoint, &Credentials::redact_url(endpoint));
+    }
+    fn rewind(mut download: &File) -> Result<()> {
+        return download
+            .seek(SeekFrom::Start(0))
+            .map(|_| ())
+            .map_err(|_| error("IO error rewinding downloaded template"));
+    }
+    /// Fetch the signature published next to a template, at the template url with .sig appended.
+    /// A missing signature means the template is unsigned. Unless signatures are required, so does
+    /// any other failure to fetch it, since servers like S3 answer 403 for missing files
+    fn fetch_signature(
+        config: &Config,
+        agent: &Agent,
+        endpoint: &str,
+    ) -> Result<Option<TemplateSignature>> {
+        let signature_endpoint = format!("{}.{}", endpoint, FOLDR_SIGNATURE_EXTENSION);
+        let request = Self::get(config, agent, &signature_endpoint);
+        let content = match request
+            .call()
+            .and_then(|mut response| response.body_mut().read_to_vec())
+        {
+            Ok(content) => content,
+            Err(ureq::Error::StatusCode(404)) => return Ok(None),
+            Err(e) if config.require_signatures => {
+                return Err(error(&format!(
+                    "Network error while fetching template signature over http: {}",
+                    Self::redact_error(&e, &signature_endpoint)
+                )));
+            }
+            Err(e) => {
+                eprintln!(
+                    "Warning: unable to fetch template signature, treating template as unsigned: {}",
+                    Self::redact_error(&e, &signature_endpoint)
+                );
+                return Ok(None);
+            }
+        };
+        return Signing::parse_signature(&content).map(Some);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use zip::{DateTime, ZipWriter, write::SimpleFileOptions};
+
+    use super::*;
+
+    /// A template file containing [`content`], written with the modification time [`year`]
+    fn template_file(content: &str, year: u16) -> File {
+        let options = SimpleFileOptions::default()
+            .last_modified_time(DateTime::from_date_and_time(year, 1, 1, 0, 0, 0).unwrap());
+        let mut writer = ZipWriter::new(tempfile::tempfile().unwrap());
+        writer.start_file("README.md", options).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+        let mut file = writer.finish().unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        return file;
+    }
+
+    fn download(content: &str, year: u16) -> Download {
+        let mut file = template_file(content, year);
+        let digest =
+            Signing::archive_digest(&file, &ArchiveLimits::from_config(&Config::defaults()))
+                .unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        return Download {
+            file,
+            sha256: year.to_string(),
+            etag: None,
+            last_modified: None,
+            digest: Some(digest),
+        };
+    }
+
+    #[test]
+    fn refreshing_to_the_same_contents_stores_no_new_version() {
+        let template_dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            template_dir: template_dir.path().to_path_buf(),
+            ..Config::defaults()
+        };
+        let previous = Template::store(
             &config,
-            &spawn_path,
-            &mut cursor,
+            "t".into(),
+            1,
+            template_file("hello", 2001),
             vec![FOLDR_MANIFEST_FILE.into()],
-        )?;
-        return Ok(template);
+        )
+        .unwrap();
+        let refresh = |download: Download| {
+            let source = download.source("http://localhost/t.foldr");
+            return NetworkUtil::store_refresh(&config, &previous, download, None, source).unwrap();
+        };
+
+        // Built again at another time, so the file differs but the contents don't
+        assert!(refresh(download("hello", 2002)).is_none());
+        assert_eq!(Template::get_existing(&config).unwrap().len(), 1);
+
+        let updated = refresh(download("changed", 2003)).unwrap();
+        assert_eq!(updated.info.iteration, 2);
+        assert_eq!(Template::get_existing(&config).unwrap().len(), 2);
     }
 }
@@ -0,0 +1,210 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
+
+use crate::{
+    blobs::HashingWriter,
+    commands::command::{Iteration, Result, error},
+    config::Config,
+    globals::{FOLDR_MANIFEST_FILE, FOLDR_PACK_INDEX_FILE},
+    signing::{Signing, TemplateSignature},
+    sources::TemplateSource,
+    templates::{Integrity, Template, TemplateInfo},
+    zip::{ArchiveLimits, LimitedReader, ZipUtil},
+};
+
+/// A bundle of templates written by foldr export and read by foldr import, to move templates between
+/// machines as a single file. It is a zip file holding a template file for every template version,
+/// and an index listing them
+pub struct TemplatePack;
+
+#[derive(Serialize, Deserialize)]
+pub struct PackIndex {
+    pub templates: Vec<PackEntry>,
+}
+
+/// A template version stored in a pack
+#[derive(Serialize, Deserialize)]
+pub struct PackEntry {
+    pub name: String,
+    pub iteration: Iteration,
+    /// Path of the template file inside of the pack
+    pub file: String,
+    /// Sha256 of the template file, checked when importing
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<TemplateSignature>,
+    /// Where the template was fetched from, so it can still be refreshed after importing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<TemplateSource>,
+}
+
+/// What to do when an imported template version already exists
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnConflict {
+    /// Keep the existing version and leave out the imported one
+    #[default]
+    Skip,
+    /// Import the template as a new version after the most recent existing one
+    Renumber,
+    /// Replace the existing version with the imported one
+    Overwrite,
+}
+
+impl TemplatePack {
+    /// Write [`templates`] into a pack at [`output`]. Returns the size of the pack
+    pub fn write(config: &Config, templates: &[Template], output: &Path) -> Result<u64> {
+        let file = File::create(output).map_err(|_| error("IO error creating pack file"))?;
+        let mut writer = ZipWriter::new(file);
+        // Template files are compressed already
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let write_error = |_| error("IO error writing pack file");
+        let mut index = PackIndex { templates: vec![] };
+        for template in templates {
+            let file = format!(
+                "templates/{}/{}.foldr",
+                template.info.name, template.info.iteration
+            );
+            let archive = tempfile::NamedTempFile::new()
+                .map_err(|_| error("IO error creating temporary file"))?;
+            template.write_archive(config, &archive.path().to_path_buf())?;
+            writer.start_file(&file, options).map_err(write_error)?;
+            let mut hashing_writer = HashingWriter::new(&mut writer);
+            io::copy(&mut archive.as_file(), &mut hashing_writer)
+                .map_err(|_| error("IO error writing pack file"))?;
+            index.templates.push(PackEntry {
+                name: template.info.name.clone(),
+                iteration: template.info.iteration,
+                file,
+                sha256: hashing_writer.hash(),
+                signature: Signing::read_signature(template)?,
+                source: TemplateSource::read(template)?,
+            });
+        }
+        writer
+            .start_file(FOLDR_PACK_INDEX_FILE, SimpleFileOptions::default())
+            .map_err(write_error)?;
+        serde_json::to_writer_pretty(&mut writer, &index)
+            .map_err(|_| error("IO error writing pack index"))?;
+        let file = writer.finish().map_err(write_error)?;
+        return file
+            .metadata()
+            .map(|m| m.len())
+            .map_err(|_| error("Error querying pack file size"));
+    }
+
+    /// Open a pack and read its index
+    pub fn open(path: &Path) -> Result<(ZipArchive<BufReader<File>>, PackIndex)> {
+        let file = File::open(path)
+            .map_err(|_| error(&format!("Unable to open pack {}", path.to_string_lossy())))?;
+        let mut zip =
+            ZipArchive::new(BufReader::new(file)).map_err(|_| error("Pack file is corrupt"))?;
+        let mut index = zip
+            .by_name(FOLDR_PACK_INDEX_FILE)
+            .map_err(|_| error("Pack file has no index"))?;
+        let size = index.size();
+        let mut content = String::new();
+        LimitedReader::new(&mut index, size)
+            .read_to_string(&mut content)
+            .map_err(|_| error("Error reading pack index"))?;
+        drop(index);
+        let index = serde_json::from_str(&content).map_err(|_| error("Pack index is corrupt"))?;
+        return Ok((zip, index));
+    }
+
+    /// Copy the template file of [`entry`] out of the pack into a temporary file, checking it against
+    /// the hash in the index. Returns it rewound to the start
+    pub fn extract(zip: &mut ZipArchive<BufReader<File>>, entry: &PackEntry) -> Result<File> {
+        let mut file = zip
+            .by_name(&entry.file)
+            .map_err(|_| error("Template file is missing from the pack"))?;
+        let size = file.size();
+        let mut output =
+            tempfile::tempfile().map_err(|_| error("IO error creating temporary file"))?;
+        let mut hashing_writer = HashingWriter::new(&mut output);
+        io::copy(
+            &mut LimitedReader::new(&mut file, size),
+            &mut hashing_writer,
+        )
+        .map_err(|e| error(&format!("IO error reading template from pack: {}", e)))?;
+        let sha256 = hashing_writer.hash();
+        if !sha256.eq_ignore_ascii_case(&entry.sha256) {
+            return Err(error(&format!(
+                "Template file does not match the hash in the pack index\nExpected: {}\nActual:   {}",
+                entry.sha256, sha256
+            )));
+        }
+        output
+            .seek(SeekFrom::Start(0))
+            .map_err(|_| error("IO error reading template from pack"))?;
+        return Ok(output);
+    }
+
+    /// Store a template file extracted from a pack as [`iteration`] of its template, replacing the
+    /// files of [`replace`] when passed. The template is checked against its manifest and its
+    /// signature is checked like the one of a downloaded template, before anything is replaced
+    pub fn store(
+        config: &Config,
+        entry: &PackEntry,
+        mut file: File,
+        iteration: Iteration,
+        replace: Option<&Template>,
+    ) -> Result<Template> {
+        let mut zip = ZipArchive::new(&file).map_err(|_| error("Template file is corrupt"))?;
+        ArchiveLimits::from_config(config).check(&mut zip)?;
+        let hashes = ZipUtil::hash_archive(&mut zip)?;
+        if let Ok(info) = ZipUtil::read_manifest(&mut zip)
+            && let Integrity::Invalid(problems) = info.check_integrity(&hashes)
+        {
+            return Err(error(&format!(
+                "Template file does not match the hashes in its manifest:\n{}",
+                problems.join("\n")
+            )));
+        }
+        let digest = TemplateInfo::compute_digest(&hashes);
+        Signing::check_download(config, entry.signature.as_ref(), &digest)?;
+        file.seek(SeekFrom::Start(0))
+            .map_err(|_| error("IO error reading template from pack"))?;
+        if let Some(replace) = replace {
+            replace.ensure_writable(config)?;
+        }
+        // The new file replaces the old one only once it is complete, what is left of the old
+        // version is removed after that
+        let template = Template::store(
+            config,
+            entry.name.clone(),
+            iteration,
+            file,
+            vec![FOLDR_MANIFEST_FILE.into()],
+        )?;
+        if let Some(replace) = replace {
+            replace.remove_sidecars()?;
+            if replace.filename != template.filename {
+                fs::remove_file(&replace.filename)
+                    .map_err(|_| error("IO error removing replaced template file"))?;
+            }
+        }
+        if let Some(signature) = &entry.signature {
+            Signing::write_signature(&template, signature)?;
+        }
+        if let Some(source) = &entry.source {
+            source.write(&template)?;
+        }
+        return Ok(template);
+    }
+
+    /// Sha256 of a pack file, printed after exporting so it can be checked on the other machine
+    pub fn hash(path: &Path) -> Result<String> {
+        let mut file = File::open(path).map_err(|_| error("IO error opening pack file"))?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher).map_err(|_| error("IO error hashing pack file"))?;
+        return Ok(format!("{:x}", hasher.finalize()));
+    }
+}
@@ -0,0 +1,175 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::command::{Iteration, Result, error},
+    config::{Config, ExpandablePathBuf},
+    globals,
+    network::NetworkUtil,
+};
+
+/// A template registry: a static index.json listing templates, which any static file server can host
+pub struct Registry;
+
+/// The index.json of a registry
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RegistryIndex {
+    #[serde(default)]
+    pub templates: Vec<RegistryEntry>,
+}
+
+/// A single iteration of a template listed in a registry index
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    pub name: String,
+    pub iteration: Iteration,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Url of the template file. Relative urls are resolved against the url of the index
+    pub url: String,
+    /// Sha256 of the template file. Downloads that don't match it are refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl RegistryEntry {
+    /// Whether [`query`] appears in the name, description or tags of the entry, ignoring case
+    pub fn matches(&self, query: &str) -> bool {
+        let query = query.to_lowercase();
+        return self.name.to_lowercase().contains(&query)
+            || self
+                .description
+                .as_ref()
+                .is_some_and(|d| d.to_lowercase().contains(&query))
+            || self.tags.iter().any(|t| t.to_lowercase().contains(&query));
+    }
+}
+
+impl Registry {
+    /// Get all added registries, by name
+    pub fn get_all() -> Result<BTreeMap<String, String>> {
+        let path = PathBuf::from(globals::FOLDR_REGISTRIES_FILE).expand();
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|_| error("IO error reading registries file"))?;
+        return serde_json::from_str(&content).map_err(|_| error("Registries file is corrupt"));
+    }
+
+    pub fn save_all(registries: &BTreeMap<String, String>) -> Result<()> {
+        let path = PathBuf::from(globals::FOLDR_REGISTRIES_FILE).expand();
+        fs::create_dir_all(path.parent().unwrap())
+            .map_err(|_| error("IO error creating foldr directory"))?;
+        return fs::write(&path, serde_json::to_string_pretty(registries).unwrap())
+            .map_err(|_| error("IO error writing registries file"));
+    }
+
+    /// Get the url of the index of a registry. Accepts both the url of the index itself
+    /// and the url of the directory it is in
+    pub fn index_url(url: &str) -> String {
+        if url.ends_with(".json") {
+            return url.to_string();
+        }
+        return format!("{}/index.json", url.trim_end_matches('/'));
+    }
+
+    /// Download the index of the registry [`name`]
+    pub fn fetch_index(config: &Config, name: &str) -> Result<(String, RegistryIndex)> {
+        let registries = Self::get_all()?;
+        let Some(url) = registries.get(name) else {
+            return Err(error(&format!(
+                "No registry named {}. Add one with foldr registry add",
+                name
+            )));
+        };
+        let index_url = Self::index_url(url);
+        let index: RegistryIndex = NetworkUtil::fetch_json(config, &index_url)?;
+        return Ok((index_url, index));
+    }
+
+    /// Split a `<registry>/<name>[@iteration]` reference into its parts. Returns None when the first
+    /// path segment is not an added registry
+    pub fn parse_reference(reference: &str) -> Result<Option<(String, String, Option<Iteration>)>> {
+        let Some((registry, rest)) = reference.split_once('/') else {
+            return Ok(None);
+        };
+        if !Self::get_all()?.contains_key(registry) {
+            return Ok(None);
+        }
+        let (name, iteration) = match rest.rsplit_once('@') {
+            Some((name, iteration)) => {
+                let iteration = iteration
+                    .parse::<Iteration>()
+                    .map_err(|_| error(&format!("Invalid template version {}", iteration)))?;
+                (name, Some(iteration))
+            }
+            None => (rest, None),
+        };
+        return Ok(Some((registry.into(), name.into(), iteration)));
+    }
+
+    /// Find a template in the index of [`registry`]. Returns the entry with its url resolved,
+    /// using the most recent iteration when none is passed
+    pub fn resolve(
+        config: &Config,
+        registry: &str,
+        name: &str,
+        iteration: Option<Iteration>,
+    ) -> Result<RegistryEntry> {
+        let (index_url, index) = Self::fetch_index(config, registry)?;
+        let Some(mut entry) = index
+            .templates
+            .into_iter()
+            .filter(|e| e.name == name && iteration.is_none_or(|i| e.iteration == i))
+            .max_by_key(|e| e.iteration)
+        else {
+            return Err(error(&format!(
+                "Template or template version not found in registry {}",
+                registry
+            )));
+        };
+        entry.url = Self::resolve_url(&index_url, &entry.url);
+        return Ok(entry);
+    }
+
+    /// Get the url of the directory the index of a registry is in, which template urls are relative to
+    pub fn base_url(url: &str) -> String {
+        let index_url = Self::index_url(url);
+        return index_url[..index_url.rfind('/').unwrap_or(index_url.len())].to_string();
+    }
+
+    /// Percent encode a template name for use in a url, keeping the / between groups
+    pub fn encode_name(name: &str) -> String {
+        let mut encoded = String::new();
+        for byte in name.bytes() {
+            if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+                encoded.push(byte as char);
+            } else {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        return encoded;
+    }
+
+    /// Resolve a url from an index against the url of the index
+    pub fn resolve_url(index_url: &str, url: &str) -> String {
+        if url.contains("://") {
+            return url.to_string();
+        }
+        if url.starts_with('/') {
+            let scheme_end = index_url.find("://").map_or(0, |i| i + 3);
+            let authority_end = index_url[scheme_end..]
+                .find('/')
+                .map_or(index_url.len(), |i| scheme_end + i);
+            return format!("{}{}", &index_url[..authority_end], url);
+        }
+        let base = index_url
+            .rfind('/')
+            .map_or(index_url, |i| &index_url[..i + 1]);
+        return format!("{}{}", base, url.trim_start_matches("./"));
+    }
+}
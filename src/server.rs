@@ -0,0 +1,456 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sha2::{Digest, Sha256};
+use tempfile::NamedTempFile;
+
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+
+use crate::{
+    blobs::BlobStore,
+    commands::command::{Iteration, Result, error},
+    config::Config,
+    globals,
+    registry::{Registry, RegistryEntry, RegistryIndex},
+    signing::Signing,
+    templates::Template,
+    zip::ArchiveOptions,
+};
+
+/// Serves the local template store as a registry, which foldr fetch and foldr search can use
+pub struct TemplateServer {
+    config: Config,
+    /// Token clients must send as bearer token. Anyone can read when not set
+    token: Option<String>,
+    /// Token clients must send as bearer token to publish templates. Publishing is disabled when not set
+    write_token: Option<String>,
+    /// Sha256 of the served template file per template version, with the modification time of the
+    /// stored file it was computed for
+    hashes: RefCell<HashMap<(String, Iteration), (SystemTime, String)>>,
+}
+
+/// Maximum size of an uploaded signature file
+const MAX_SIGNATURE_SIZE: u64 = 64 * 1024;
+
+impl TemplateServer {
+    pub fn new(config: Config, token: Option<String>, write_token: Option<String>) -> Self {
+        return Self {
+            config,
+            token,
+            write_token,
+            hashes: RefCell::new(HashMap::new()),
+        };
+    }
+
+    /// Serve requests on [`bind`] until the process is stopped
+    pub fn run(&self, bind: &str) -> Result<()> {
+        let server = Server::http(bind)
+            .map_err(|e| error(&format!("Unable to listen on {}: {}", bind, e)))?;
+        println!(
+            "Serving templates from {} on http://{}",
+            self.config
+                .template_stores()
+                .iter()
+                .map(|store| store.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", "),
+            bind
+        );
+        println!(
+            "Add it as a registry with: foldr registry add <name> http://{}",
+            bind
+        );
+        if self.write_token.is_some() {
+            println!("Publishing templates is enabled");
+        }
+        for mut request in server.incoming_requests() {
+            let method = request.method().clone();
+            let url = request.url().to_string();
+            let response = self.handle(&mut request);
+            println!("{} {} {}", method, url, response.status_code().0);
+            if let Err(e) = request.respond(response) {
+                println!("Error sending response: {}", e);
+            }
+        }
+        return Ok(());
+    }
+
+    fn handle(&self, request: &mut Request) -> ResponseBox {
+        let path = decode_path(request.url().split(['?', '#']).next().unwrap_or("/"));
+        if *request.method() == Method::Put {
+            if self.write_token.is_none() {
+                return Self::status(405, "Publishing is not enabled on this server");
+            }
+            if !Self::has_token(request, &self.write_token) {
+                return Self::unauthorized();
+            }
+            let Some(file) = path.strip_prefix("/templates/") else {
+                return Self::status(404, "Not found");
+            };
+            return self.upload(request, file).unwrap_or_else(|e| {
+                println!("Error handling upload of {}: {}", path, e.message);
+                Self::status(500, "Internal server error")
+            });
+        }
+        if self.token.is_some()
+            && !Self::has_token(request, &self.token)
+            && !Self::has_token(request, &self.write_token)
+        {
+            return Self::unauthorized();
+        }
+        if *request.method() != Method::Get && *request.method() != Method::Head {
+            return Self::status(405, "Method not allowed");
+        }
+        let result = if path == "/" || path == "/index.json" {
+            self.index()
+        } else if let Some(file) = path.strip_prefix("/templates/") {
+            self.template_file(request, file)
+        } else {
+            Ok(Self::status(404, "Not found"))
+        };
+        return result.unwrap_or_else(|e| {
+            println!("Error handling {}: {}", path, e.message);
+            Self::status(500, "Internal server error")
+        });
+    }
+
+    fn has_token(request: &Request, token: &Option<String>) -> bool {
+        let Some(token) = token else {
+            return false;
+        };
+        let expected = format!("Bearer {}", token);
+        return request.headers().iter().any(|h| {
+            h.field.equiv("Authorization")
+                && constant_time_eq(h.value.as_bytes(), expected.as_bytes())
+        });
+    }
+
+    /// Build the registry index listing every stored template
+    fn index(&self) -> Result<ResponseBox> {
+        let mut templates = Template::get_existing(&self.config)?;
+        templates.sort_by_key(|t| t.info.iteration);
+        templates.sort_by_key(|t| t.info.name.clone());
+        let mut index = RegistryIndex::default();
+        for template in templates {
+            // Templates in the blob store are assembled per download, so hashing them means building them
+            let sha256 = if template.is_tree() {
+                None
+            } else {
+                Some(match self.cached_hash(&template)? {
+                    Some(hash) => hash,
+                    None => {
+                        let hash = template.archive_hash()?;
+                        self.cache_hash(&template, &hash)?;
+                        hash
+                    }
+                })
+            };
+            index.templates.push(RegistryEntry {
+                url: format!(
+                    "templates/{}/{}.{}",
+                    Registry::encode_name(&template.info.name),
+                    template.info.iteration,
+                    globals::FOLDR_TEMPLATE_EXTENSION
+                ),
+                name: template.info.name,
+                iteration: template.info.iteration,
+                description: None,
+                tags: vec![],
+                sha256,
+            });
+        }
+        return Ok(
+            Response::from_string(serde_json::to_string_pretty(&index).unwrap())
+                .with_header(Self::header("Content-Type", "application/json"))
+                .boxed(),
+        );
+    }
+
+    /// Split `<name>/<version>.foldr[.sig]` into the name, the version and whether the signature is meant
+    fn parse_template_path(file: &str) -> Option<(&str, &str, bool)> {
+        let (name, file_name) = file.rsplit_once('/')?;
+        let (file_name, signature) =
+            match file_name.strip_suffix(&format!(".{}", globals::FOLDR_SIGNATURE_EXTENSION)) {
+                Some(file_name) => (file_name, true),
+                None => (file_name, false),
+            };
+        let version = file_name.strip_suffix(&format!(".{}", globals::FOLDR_TEMPLATE_EXTENSION))?;
+        if name.is_empty() || name.split('/').any(|part| part.is_empty()) {
+            return None;
+        }
+        return Some((name, version, signature));
+    }
+
+    /// Serve `<name>/<iteration>.foldr`, `<name>/latest.foldr` or the signature of either with .sig appended.
+    /// Template files are sent with an ETag and Last-Modified header, and not sent again when the client
+    /// already has them
+    fn template_file(&self, request: &Request, file: &str) -> Result<ResponseBox> {
+        let Some((name, version, signature)) = Self::parse_template_path(file) else {
+            return Ok(Self::status(404, "Not found"));
+        };
+        let template = if version == "latest" {
+            Template::get_existing_by_name(&self.config, name)?
+        } else {
+            let Ok(iteration) = version.parse::<Iteration>() else {
+                return Ok(Self::status(404, "Not found"));
+            };
+            Template::get_existing_by_name_and_iteration(&self.config, name, iteration)?
+        };
+        let Some(template) = template else {
+            return Ok(Self::status(404, "Template or template version not found"));
+        };
+
+        if signature {
+            return Ok(match Signing::read_signature(&template)? {
+                Some(signature) => {
+                    Response::from_string(serde_json::to_string_pretty(&signature).unwrap())
+                        .with_header(Self::header("Content-Type", "application/json"))
+                        .boxed()
+                }
+                None => Self::status(404, "Template is not signed"),
+            });
+        }
+
+        let mut archive = None;
+        let hash = match self.cached_hash(&template)? {
+            Some(hash) => hash,
+            None => {
+                let mut file = self.open_template_file(&template)?;
+                let hash = hash_file(&mut file)?;
+                self.cache_hash(&template, &hash)?;
+                archive = Some(file);
+                hash
+            }
+        };
+        let etag = format!("\"{}\"", hash);
+        let modified = Self::modified(&template)?;
+        let headers = [
+            Self::header("ETag", &etag),
+            Self::header("Last-Modified", &httpdate::fmt_http_date(modified)),
+        ];
+        if Self::is_not_modified(request, &etag, modified) {
+            let mut response = Response::empty(304);
+            for header in headers {
+                response.add_header(header);
+            }
+            return Ok(response.boxed());
+        }
+        let archive = match archive {
+            Some(archive) => archive,
+            None => self.open_template_file(&template)?,
+        };
+        let mut response = Response::from_file(archive)
+            .with_header(Self::header("Content-Type", "application/octet-stream"));
+        for header in headers {
+            response.add_header(header);
+        }
+        return Ok(response.boxed());
+    }
+
+    /// Open the template file of [`template`]. Templates in the blob store are written into a temporary
+    /// file first, with fixed timestamps so every download of a version is identical
+    fn open_template_file(&self, template: &Template) -> Result<File> {
+        if !template.is_tree() {
+            return File::open(&template.filename)
+                .map_err(|_| error("IO error while opening template file"));
+        }
+        let mut file =
+            tempfile::tempfile().map_err(|_| error("IO error creating temporary file"))?;
+        let options = ArchiveOptions {
+            reproducible: true,
+            ..ArchiveOptions::from_config(&self.config)
+        };
+        BlobStore::write_archive(template, &options, &mut file)?;
+        file.rewind()
+            .map_err(|_| error("IO error reading template file"))?;
+        return Ok(file);
+    }
+
+    /// Modification time of the stored template, truncated to the seconds http dates have
+    fn modified(template: &Template) -> Result<SystemTime> {
+        let modified = fs::metadata(&template.filename)
+            .and_then(|m| m.modified())
+            .map_err(|_| error("IO error reading template file"))?;
+        let seconds = modified
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        return Ok(UNIX_EPOCH + Duration::from_secs(seconds));
+    }
+
+    fn cached_hash(&self, template: &Template) -> Result<Option<String>> {
+        let modified = Self::modified(template)?;
+        return Ok(self
+            .hashes
+            .borrow()
+            .get(&(template.info.name.clone(), template.info.iteration))
+            .filter(|(cached_modified, _)| *cached_modified == modified)
+            .map(|(_, hash)| hash.clone()));
+    }
+
+    fn cache_hash(&self, template: &Template, hash: &str) -> Result<()> {
+        let modified = Self::modified(template)?;
+        self.hashes.borrow_mut().insert(
+            (template.info.name.clone(), template.info.iteration),
+            (modified, hash.to_string()),
+        );
+        return Ok(());
+    }
+
+    /// Whether the client already has the version of a template file with [`etag`], going by its
+    /// If-None-Match header, or If-Modified-Since when it doesn't send one
+    fn is_not_modified(request: &Request, etag: &str, modified: SystemTime) -> bool {
+        let header = |field: &'static str| {
+            request
+                .headers()
+                .iter()
+                .find(|h| h.field.equiv(field))
+                .map(|h| h.value.as_str().to_string())
+        };
+        if let Some(if_none_match) = header("If-None-Match") {
+            return if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag);
+        }
+        return header("If-Modified-Since")
+            .and_then(|since| httpdate::parse_http_date(&since).ok())
+            .is_some_and(|since| modified <= since);
+    }
+
+    /// Store an uploaded `<name>/<iteration>.foldr`, or the signature of a stored template with .sig appended.
+    /// Existing iterations and signatures are never replaced
+    fn upload(&self, request: &mut Request, file: &str) -> Result<ResponseBox> {
+        let Some((name, version, signature)) = Self::parse_template_path(file) else {
+            return Ok(Self::status(404, "Not found"));
+        };
+        let Ok(iteration) = version.parse::<Iteration>() else {
+            return Ok(Self::status(
+                400,
+                "Templates can only be published with a version number",
+            ));
+        };
+        let existing = Template::get_existing_by_name_and_iteration(&self.config, name, iteration)?;
+
+        if signature {
+            let Some(template) = existing else {
+                return Ok(Self::status(404, "Template or template version not found"));
+            };
+            if Signing::read_signature(&template)?.is_some() {
+                return Ok(Self::status(409, "Template version is already signed"));
+            }
+            let mut content = Vec::new();
+            if Self::read_body(request, MAX_SIGNATURE_SIZE, &mut content)? {
+                return Ok(Self::status(413, "Signature is too large"));
+            }
+            let Ok(parsed) = Signing::parse_signature(&content) else {
+                return Ok(Self::status(400, "Signature is corrupt"));
+            };
+            let Some(digest) = &template.info.digest else {
+                return Ok(Self::status(400, "Template has no recorded digest to sign"));
+            };
+            if let Err(e) = Signing::verify_signature(&parsed, digest) {
+                return Ok(Self::status(400, &e.message));
+            }
+            Signing::write_signature(&template, &parsed)?;
+            return Ok(Self::status(201, "Signature stored"));
+        }
+
+        if existing.is_some() {
+            return Ok(Self::status(
+                409,
+                &format!("Version {} of {} already exists", iteration, name),
+            ));
+        }
+        let mut upload =
+            NamedTempFile::new().map_err(|_| error("IO error creating temporary upload file"))?;
+        if Self::read_body(request, self.config.max_download_size, upload.as_file_mut())? {
+            return Ok(Self::status(413, "Template is too large"));
+        }
+        upload
+            .as_file_mut()
+            .rewind()
+            .map_err(|_| error("IO error reading uploaded template"))?;
+        return Ok(
+            match Template::store(
+                &self.config,
+                name.into(),
+                iteration,
+                upload.as_file_mut(),
+                vec![globals::FOLDR_MANIFEST_FILE.into()],
+            ) {
+                Ok(_) => Self::status(201, "Template stored"),
+                Err(e) => Self::status(400, &e.message),
+            },
+        );
+    }
+
+    /// Copy the request body to [`output`]. Returns true when the body is larger than [`limit`],
+    /// where 0 means no limit
+    fn read_body<W: Write>(request: &mut Request, limit: u64, output: &mut W) -> Result<bool> {
+        let limit = if limit == 0 { u64::MAX } else { limit };
+        let copied = io::copy(
+            &mut request.as_reader().take(limit.saturating_add(1)),
+            output,
+        )
+        .map_err(|_| error("IO error reading upload"))?;
+        return Ok(copied > limit);
+    }
+
+    fn unauthorized() -> ResponseBox {
+        return Response::from_string("Unauthorized\n")
+            .with_status_code(401)
+            .with_header(Self::header("WWW-Authenticate", "Bearer"))
+            .boxed();
+    }
+
+    fn status(code: u16, message: &str) -> ResponseBox {
+        return Response::from_string(format!("{}\n", message))
+            .with_status_code(code)
+            .boxed();
+    }
+
+    fn header(field: &str, value: &str) -> Header {
+        return Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap();
+    }
+}
+
+fn decode_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = path.get(i + 1..i + 3)
+            && let Ok(byte) = u8::from_str_radix(hex, 16)
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    return String::from_utf8_lossy(&decoded).into_owned();
+}
+
+/// Get the sha256 hash of [`file`], rewinding it afterwards
+fn hash_file(file: &mut File) -> Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(file, &mut hasher).map_err(|_| error("IO error while hashing template file"))?;
+    file.rewind()
+        .map_err(|_| error("IO error while hashing template file"))?;
+    return Ok(format!("{:x}", hasher.finalize()));
+}
+
+/// Compare two secrets without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    return a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0;
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{Read, Seek, Write},
+    path::PathBuf,
+};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use zip::ZipArchive;
+
+use crate::{
+    commands::command::{Result, error},
+    config::{Config, ExpandablePathBuf},
+    globals,
+    templates::{Template, TemplateInfo},
+    zip::{ArchiveLimits, ZipUtil},
+};
+
+/// Ed25519 signing of template digests, and the store of trusted publisher keys
+pub struct Signing;
+
+/// Signature over the digest of a template. Stored next to the template file it signs
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TemplateSignature {
+    /// Hex encoded public key of the signer
+    pub public_key: String,
+    /// Hex encoded ed25519 signature over the digest
+    pub signature: String,
+    /// The template digest that was signed
+    pub digest: String,
+}
+
+impl Signing {
+    fn key_path(name: &str, extension: &str) -> Result<PathBuf> {
+        // Key names become file names in the key directory, so they can't contain a path
+        if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+            return Err(error(&format!(
+                "Invalid key name {}. Key names can't contain path separators or ..",
+                name
+            )));
+        }
+        return Ok(PathBuf::from(globals::FOLDR_KEYS_DIR)
+            .expand()
+            .join(format!("{}.{}", name, extension)));
+    }
+
+    /// Generate a new signing key pair under [`name`]. Returns the hex encoded public key
+    pub fn generate_key(name: &str, overwrite: bool) -> Result<String> {
+        let secret_path = Self::key_path(name, "key")?;
+        if secret_path.exists() && !overwrite {
+            return Err(error(&format!(
+                "A key named {} already exists. Use --force to overwrite it",
+                name
+            )));
+        }
+        let mut secret = [0u8; 32];
+        getrandom::fill(&mut secret).map_err(|_| error("Unable to gather randomness for key"))?;
+        let signing_key = SigningKey::from_bytes(&secret);
+        let public_key = to_hex(signing_key.verifying_key().as_bytes());
+
+        fs::create_dir_all(secret_path.parent().unwrap())
+            .map_err(|_| error("IO error creating key directory"))?;
+        if secret_path.exists() {
+            fs::remove_file(&secret_path).map_err(|_| error("IO error removing old secret key"))?;
+        }
+        // Created readable by the owner only, so the key is never readable by others
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(&secret_path)
+            .and_then(|mut file| file.write_all(to_hex(&secret).as_bytes()))
+            .map_err(|_| error("IO error writing secret key"))?;
+        fs::write(Self::key_path(name, "pub")?, &public_key)
+            .map_err(|_| error("IO error writing public key"))?;
+        return Ok(public_key);
+    }
+
+    fn load_signing_key(name: &str) -> Result<SigningKey> {
+        let content = fs::read_to_string(Self::key_path(name, "key")?).map_err(|_| {
+            error(&format!(
+                "No key named {} found. Create one with foldr keygen",
+                name
+            ))
+        })?;
+        let secret: [u8; 32] = from_hex(content.trim())?
+            .try_into()
+            .map_err(|_| error(&format!("Key {} is corrupt", name)))?;
+        return Ok(SigningKey::from_bytes(&secret));
+    }
+
+    /// Sign the digest of a stored template with the key [`key_name`], and store the signature next to it
+    pub fn sign(template: &Template, key_name: &str) -> Result<TemplateSignature> {
+        let Some(digest) = &template.info.digest else {
+            return Err(error(
+                "Template has no recorded digest to sign. Save or update it again to record one",
+            ));
+        };
+        let signing_key = Self::load_signing_key(key_name)?;
+        let signature = TemplateSignature {
+            public_key: to_hex(signing_key.verifying_key().as_bytes()),
+            signature: to_hex(&signing_key.sign(digest.as_bytes()).to_bytes()),
+            digest: digest.clone(),
+        };
+        Self::write_signature(template, &signature)?;
+        return Ok(signature);
+    }
+
+    pub fn signature_path(template: &Template) -> PathBuf {
+        return template.sidecar_path(globals::FOLDR_SIGNATURE_EXTENSION);
+    }
+
+    pub fn write_signature(template: &Template, signature: &TemplateSignature) -> Result<()> {
+        return fs::write(
+            Self::signature_path(template),
+            serde_json::to_string_pretty(signature).unwrap(),
+        )
+        .map_err(|_| error("IO error writing template signature"));
+    }
+
+    /// Read the signature stored next to a template, if it has one
+    pub fn read_signature(template: &Template) -> Result<Option<TemplateSignature>> {
+        let path = Self::signature_path(template);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|_| error("IO error reading template signature"))?;
+        return Self::parse_signature(content.as_bytes()).map(Some);
+    }
+
+    pub fn parse_signature(content: &[u8]) -> Result<TemplateSignature> {
+        return serde_json::from_slice(content).map_err(|_| error("Template signature is corrupt"));
+    }
+
+    /// Check that [`signature`] is a valid signature over [`digest`]
+    pub fn verify_signature(signature: &TemplateSignature, digest: &str) -> Result<()> {
+        if signature.digest != digest {
+            return Err(error("Signature was made for different template contents"));
+        }
+        let public_key: [u8; 32] = from_hex(&signature.public_key)?
+            .try_into()
+            .map_err(|_| error("Signature contains an invalid public key"))?;
+        let verifying_key = VerifyingKey::from_bytes(&public_key)
+            .map_err(|_| error("Signature contains an invalid public key"))?;
+        let signature_bytes: [u8; 64] = from_hex(&signature.signature)?
+            .try_into()
+            .map_err(|_| error("Signature is malformed"))?;
+        return verifying_key
+            .verify(digest.as_bytes(), &Signature::from_bytes(&signature_bytes))
+            .map_err(|_| error("Template signature is invalid"));
+    }
+
+    /// Compute the digest of a template file from its contents. The archive limits are checked first,
+    /// since the template is not trusted yet
+    pub fn archive_digest<R: Read + Seek>(stream: R, limits: &ArchiveLimits) -> Result<String> {
+        let mut zip = ZipArchive::new(stream).map_err(|_| error("Template file is corrupt"))?;
+        limits.check(&mut zip)?;
+        return Ok(TemplateInfo::compute_digest(&ZipUtil::hash_archive(
+            &mut zip,
+        )?));
+    }
+
+    /// Check the signature of a downloaded template. Invalid signatures are always rejected, unsigned
+    /// templates and unknown signers only when require_signatures is set in the config
+    pub fn check_download(
+        config: &Config,
+        signature: Option<&TemplateSignature>,
+        digest: &str,
+    ) -> Result<()> {
+        let trusted_keys = match signature {
+            Some(_) => Self::trusted_keys()?,
+            None => BTreeMap::new(),
+        };
+        return Self::check_signature(config, signature, digest, &trusted_keys);
+    }
+
+    fn check_signature(
+        config: &Config,
+        signature: Option<&TemplateSignature>,
+        digest: &str,
+        trusted_keys: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        let Some(signature) = signature else {
+            if config.require_signatures {
+                return Err(error(
+                    "Template is not signed, and unsigned templates are not allowed by config",
+                ));
+            }
+            return Ok(());
+        };
+        Self::verify_signature(signature, digest)?;
+        match Self::find_trusted(trusted_keys, &signature.public_key) {
+            Some(name) => eprintln!("Template signed by trusted key {}", name),
+            None => {
+                if config.require_signatures {
+                    return Err(error(&format!(
+                        "Template is signed by unknown key {}, which is not in the trusted keys",
+                        signature.public_key
+                    )));
+                }
+                eprintln!(
+                    "Warning: template is signed by unknown key {}",
+                    signature.public_key
+                );
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn trusted_keys() -> Result<BTreeMap<String, String>> {
+        let path = PathBuf::from(globals::FOLDR_TRUSTED_KEYS_FILE).expand();
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|_| error("IO error reading trusted keys"))?;
+        return serde_json::from_str(&content).map_err(|_| error("Trusted keys file is corrupt"));
+    }
+
+    pub fn save_trusted_keys(keys: &BTreeMap<String, String>) -> Result<()> {
+        let path = PathBuf::from(globals::FOLDR_TRUSTED_KEYS_FILE).expand();
+        fs::create_dir_all(path.parent().unwrap())
+            .map_err(|_| error("IO error creating foldr directory"))?;
+        return fs::write(&path, serde_json::to_string_pretty(keys).unwrap())
+            .map_err(|_| error("IO error writing trusted keys"));
+    }
+
+    /// Get the name a public key is trusted under, if it is trusted
+    pub fn trusted_name(public_key: &str) -> Result<Option<String>> {
+        return Ok(Self::find_trusted(&Self::trusted_keys()?, public_key));
+    }
+
+    fn find_trusted(trusted_keys: &BTreeMap<String, String>, public_key: &str) -> Option<String> {
+        return trusted_keys
+            .iter()
+            .find(|(_, key)| key.eq_ignore_ascii_case(public_key))
+            .map(|(name, _)| name.clone());
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    return bytes.iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(error("Invalid hex string"));
+    }
+    return (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| error("Invalid hex string")))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    fn config(require_signatures: bool) -> Config {
+        return Config {
+            require_signatures,
+            ..Config::defaults()
+        };
+    }
+
+    fn signed(digest: &str) -> TemplateSignature {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        return TemplateSignature {
+            public_key: to_hex(signing_key.verifying_key().as_bytes()),
+            signature: to_hex(&signing_key.sign(digest.as_bytes()).to_bytes()),
+            digest: digest.to_string(),
+        };
+    }
+
+    fn trusting(signature: &TemplateSignature) -> BTreeMap<String, String> {
+        return BTreeMap::from([("publisher".to_string(), signature.public_key.clone())]);
+    }
+
+    #[test]
+    fn unsigned_templates_are_rejected_when_signatures_are_required() {
+        let none = BTreeMap::new();
+        assert!(Signing::check_signature(&config(true), None, DIGEST, &none).is_err());
+        assert!(Signing::check_signature(&config(false), None, DIGEST, &none).is_ok());
+    }
+
+    #[test]
+    fn signatures_from_unknown_keys_are_rejected_when_signatures_are_required() {
+        let signature = signed(DIGEST);
+        let none = BTreeMap::new();
+        assert!(Signing::check_signature(&config(true), Some(&signature), DIGEST, &none).is_err());
+        assert!(Signing::check_signature(&config(false), Some(&signature), DIGEST, &none).is_ok());
+        assert!(
+            Signing::check_signature(
+                &config(true),
+                Some(&signature),
+                DIGEST,
+                &trusting(&signature)
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn invalid_signatures_are_always_rejected() {
+        let other_digest = DIGEST.replace("9", "8");
+        let mut tampered = signed(DIGEST);
+        // Signature of other contents, with the digest changed to match the download
+        tampered.signature = signed(&other_digest).signature;
+        let mut garbage = signed(DIGEST);
+        garbage.signature = "zz".repeat(64);
+        let mut short_key = signed(DIGEST);
+        short_key.public_key.truncate(10);
+        for (signature, digest) in [
+            (signed(&other_digest), DIGEST),
+            (tampered, DIGEST),
+            (garbage, DIGEST),
+            (short_key, DIGEST),
+        ] {
+            for require_signatures in [false, true] {
+                assert!(
+                    Signing::check_signature(
+                        &config(require_signatures),
+                        Some(&signature),
+                        digest,
+                        &trusting(&signature)
+                    )
+                    .is_err()
+                );
+            }
+        }
+    }
+}
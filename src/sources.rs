@@ -0,0 +1,57 @@
+use std::fs;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::command::{Result, error},
+    globals,
+    templates::Template,
+};
+
+/// Where a fetched template was downloaded from, with the validators the server sent for it.
+/// Stored next to the template file, so foldr refresh can ask the server whether it changed
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TemplateSource {
+    /// Url the template file was downloaded from
+    pub url: String,
+    /// The `<registry>/<name>` reference the template was fetched with, if it came from a registry.
+    /// Refreshing looks up the most recent version in the registry again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// Sha256 of the downloaded template file
+    pub sha256: String,
+    /// Whether the single top level directory of the downloaded archive was removed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strip_top_level: bool,
+}
+
+impl TemplateSource {
+    pub fn path(template: &Template) -> std::path::PathBuf {
+        return template.sidecar_path(globals::FOLDR_SOURCE_EXTENSION);
+    }
+
+    pub fn write(&self, template: &Template) -> Result<()> {
+        return fs::write(
+            Self::path(template),
+            serde_json::to_string_pretty(self).unwrap(),
+        )
+        .map_err(|_| error("IO error writing template source"));
+    }
+
+    /// Read the source stored next to a template. Returns None for templates that were not fetched
+    pub fn read(template: &Template) -> Result<Option<TemplateSource>> {
+        let path = Self::path(template);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|_| error("IO error reading template source"))?;
+        return serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|_| error("Template source file is corrupt"));
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+
+use crate::{
+    commands::command::{Result, error},
+    config::{Config, ExpandablePathBuf},
+    globals::{FOLDR_CONFIG_FILE, FOLDR_STORE_MOVE_FILE},
+    zip::ZipUtil,
+};
+
+/// A move of the template store to another directory by foldr store move. It is recorded before
+/// anything is copied and removed when the move is done, so an interrupted move can be picked up
+/// again by running the same command
+#[derive(Serialize, Deserialize)]
+pub struct StoreMove {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+impl StoreMove {
+    fn path() -> PathBuf {
+        return PathBuf::from(FOLDR_STORE_MOVE_FILE).expand();
+    }
+
+    /// Get the move that was interrupted, if there is one
+    pub fn pending() -> Result<Option<StoreMove>> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content =
+            fs::read_to_string(&path).map_err(|_| error("IO error reading store move state"))?;
+        return serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|_| error("Store move state file is corrupt"));
+    }
+
+    /// Start moving the template store to [`to`], or continue the interrupted move to it
+    pub fn start(config: &Config, to: &Path) -> Result<StoreMove> {
+        let to = std::path::absolute(to.to_path_buf().expand())
+            .map_err(|_| error("Invalid template store directory"))?;
+        if let Some(pending) = Self::pending()? {
+            if pending.to != to {
+                return Err(error(&format!(
+                    "A move of the template store to {} was interrupted. Run foldr store move {} to finish it first",
+                    pending.to.to_string_lossy(),
+                    pending.to.to_string_lossy()
+                )));
+            }
+            println!(
+                "Resuming the interrupted move from {}",
+                pending.from.to_string_lossy()
+            );
+            return Ok(pending);
+        }
+
+        let from = std::path::absolute(config.template_dir.expand())
+            .map_err(|_| error("Invalid template directory"))?;
+        if from == to {
+            return Err(error("The template store is in that directory already"));
+        }
+        if to.starts_with(&from) || from.starts_with(&to) {
+            return Err(error(
+                "The template store can't be moved into or out of one of its own directories",
+            ));
+        }
+        if to.read_dir().is_ok_and(|mut d| d.next().is_some()) {
+            return Err(error(&format!("{} is not empty", to.to_string_lossy())));
+        }
+        let store_move = StoreMove { from, to };
+        // Fails early when the config can't be updated, before anything is copied
+        store_move.read_config(config)?;
+        fs::write(
+            Self::path(),
+            serde_json::to_string_pretty(&store_move).unwrap(),
+        )
+        .map_err(|_| error("IO error writing store move state"))?;
+        return Ok(store_move);
+    }
+
+    /// Copy the template store, verify the copy, point the config file at it and remove the old
+    /// store. Returns the number of template versions moved
+    pub fn run(&self, config: &Config) -> Result<usize> {
+        // A move interrupted after the old store was removed only has to be finished
+        if self.from.exists() {
+            let files = self.copy()?;
+            println!("Copied {} files, verifying", files.len());
+            self.verify(&files)?;
+        }
+        let moved = ZipUtil::get_templates(&self.to)?.len();
+        self.write_config(config)?;
+        if self.from.exists() {
+            fs::remove_dir_all(&self.from)
+                .map_err(|_| error("IO error removing the old template store"))?;
+        }
+        fs::remove_file(Self::path()).map_err(|_| error("IO error removing store move state"))?;
+        return Ok(moved);
+    }
+
+    /// Copy every file of the old store. Files copied completely before an interruption are kept.
+    /// Returns the paths of the copied files relative to the store
+    fn copy(&self) -> Result<Vec<PathBuf>> {
+        fs::create_dir_all(&self.to)
+            .map_err(|_| error("IO error creating template store directory"))?;
+        let mut files = vec![];
+        for entry in WalkDir::new(&self.from).min_depth(1).sort_by_file_name() {
+            let entry =
+                entry.map_err(|_| error("Something went wrong traversing the template store"))?;
+            let relative_path = entry.path().strip_prefix(&self.from).unwrap().to_path_buf();
+            let target = self.to.join(&relative_path);
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&target)
+                    .map_err(|_| error("IO error creating directory in template store"))?;
+                continue;
+            }
+            let size = entry.metadata().map(|m| m.len()).ok();
+            if fs::metadata(&target).ok().map(|m| m.len()) != size {
+                fs::copy(entry.path(), &target).map_err(|_| {
+                    error(&format!(
+                        "IO error copying {}",
+                        relative_path.to_string_lossy()
+                    ))
+                })?;
+            }
+            files.push(relative_path);
+        }
+        return Ok(files);
+    }
+
+    /// Check that every copied file has the same contents as the original
+    fn verify(&self, files: &[PathBuf]) -> Result<()> {
+        for file in files {
+            if Self::hash(&self.from.join(file))? != Self::hash(&self.to.join(file))? {
+                // Copied again when the move is retried
+                let _ = fs::remove_file(self.to.join(file));
+                return Err(error(&format!(
+                    "{} does not match the original after copying it. Run the move again to retry",
+                    file.to_string_lossy()
+                )));
+            }
+        }
+        return Ok(());
+    }
+
+    fn hash(path: &Path) -> Result<String> {
+        let mut file = File::open(path)
+            .map_err(|_| error(&format!("IO error opening {}", path.to_string_lossy())))?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)
+            .map_err(|_| error(&format!("IO error reading {}", path.to_string_lossy())))?;
+        return Ok(format!("{:x}", hasher.finalize()));
+    }
+
+    /// Read the config file as it is, so keys this version of foldr doesn't know are kept. Refuses
+    /// when it doesn't point at either end of the move, like when a config file passed with
+    /// --config is in use
+    fn read_config(&self, config: &Config) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let path = PathBuf::from(FOLDR_CONFIG_FILE).expand();
+        let content = if path.exists() {
+            serde_json::from_str(
+                &fs::read_to_string(&path).map_err(|_| error("IO error reading config file"))?,
+            )
+            .map_err(|_| error("Config file is corrupt"))?
+        } else {
+            serde_json::to_value(config).map_err(|_| error("Serializer error"))?
+        };
+        let serde_json::Value::Object(content) = content else {
+            return Err(error("Config file is corrupt"));
+        };
+        let template_dir = serde_json::from_value::<Config>(content.clone().into())
+            .map(|c| c.template_dir.expand())
+            .map_err(|_| error("Config file is corrupt"))?;
+        let template_dir = std::path::absolute(template_dir).unwrap_or_default();
+        if template_dir != self.from && template_dir != self.to {
+            return Err(error(&format!(
+                "The template_dir in {} is not the template store being moved. Update the config file in use instead",
+                path.to_string_lossy()
+            )));
+        }
+        return Ok(content);
+    }
+
+    /// Point template_dir in the config file at the new store. The file is replaced in one step, so
+    /// it is never left half written
+    fn write_config(&self, config: &Config) -> Result<()> {
+        let mut content = self.read_config(config)?;
+        content.insert(
+            "template_dir".into(),
+            self.to.to_string_lossy().into_owned().into(),
+        );
+        let path = PathBuf::from(FOLDR_CONFIG_FILE).expand();
+        let mut temporary = path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(&content).unwrap())
+            .map_err(|_| error("IO error writing config file"))?;
+        fs::rename(&temporary, &path).map_err(|_| error("IO error writing config file"))?;
+        return Ok(());
+    }
+}
@@ -1,24 +1,29 @@
 use std::{
     borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
     fmt::Display,
     fs::{self, File},
-    io::{Read, Seek, Write},
-    ops::Deref,
-    path::PathBuf,
+    io::{BufReader, Read, Seek, Write},
+    path::{Path, PathBuf},
 };
 
 use bytesize::ByteSize;
 use ptree::{TreeItem, print_tree};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
 
+use crate::{
+    archives::OutputArchive,
+    blobs::{BlobStore, StorageBackend},
+    globals,
+    zip::{ArchiveLimits, ArchiveOptions, Compression, LimitedReader, ZipUtil},
+};
 use crate::{
     commands::command::{Iteration, Result, error},
     config::{Config, ExpandablePathBuf},
+    git::GitProvenance,
     globals::FOLDR_MANIFEST_FILE,
 };
-use crate::{globals, zip::ZipUtil};
 use sha2::{Digest, Sha256};
 
 /// Represents a template, including its file data and manifest
@@ -29,11 +34,49 @@ pub struct Template {
     pub filesize: ByteSize,
 }
 
+/// Where the files of a template are spawned
+pub enum SpawnTarget {
+    Directory(PathBuf),
+    /// An archive, with the files spawned into it instead of the filesystem
+    Archive(OutputArchive),
+}
+
+impl SpawnTarget {
+    /// Where the template is spawned, for messages
+    pub fn describe(&self) -> String {
+        return match self {
+            SpawnTarget::Directory(path) => path.to_string_lossy().into_owned(),
+            SpawnTarget::Archive(archive) => archive.describe(),
+        };
+    }
+}
+
 /// Represents a template manifest, stored inside of the template file
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TemplateInfo {
     pub name: String,
     pub iteration: Iteration,
+    /// Whether the original file modification times were recorded and should be restored on spawn
+    #[serde(default)]
+    pub preserve_mtime: bool,
+    /// Sha256 of every file in the template, by path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, String>,
+    /// Sha256 over all file hashes, identifying the contents of the template as a whole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+    /// The git commit the template was saved from, when saved with --git-tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitProvenance>,
+}
+
+/// Outcome of checking a template against the hashes recorded in its manifest
+pub enum Integrity {
+    /// The template was saved before hashes were recorded
+    Unrecorded,
+    Valid,
+    /// The template contents differ from the recorded hashes. Contains every problem found
+    Invalid(Vec<String>),
 }
 
 /// Represents the inner contents of a template file as a file hierarchy
@@ -119,17 +162,122 @@ impl Display for TemplateHierarchy {
 }
 
 impl Template {
-    /// Spawn the template at [`spawn_path`]
-    pub fn spawn(&self, spawn_path: &PathBuf) {
-        ZipUtil::unzip(&self, spawn_path, vec![globals::FOLDR_MANIFEST_FILE.into()]);
+    /// Spawn the template at [`target`]. Refuses to spawn a template that doesn't match its
+    /// recorded hashes, unless [`force`] is set
+    pub fn spawn(&self, config: &Config, target: &SpawnTarget, force: bool) -> Result<()> {
+        if !force && let Integrity::Invalid(problems) = self.verify()? {
+            return Err(error(&format!(
+                "Template {} version {} failed its integrity check:\n{}\nUse --force to spawn it anyway",
+                self.info.name,
+                self.info.iteration,
+                problems.join("\n")
+            )));
+        }
+        let hide_from_output = vec![globals::FOLDR_MANIFEST_FILE.into()];
+        return match target {
+            SpawnTarget::Directory(spawn_path) if self.is_tree() => {
+                BlobStore::spawn(self, spawn_path)
+            }
+            SpawnTarget::Directory(spawn_path) => {
+                ZipUtil::unzip(self, spawn_path, hide_from_output)
+            }
+            SpawnTarget::Archive(archive) => {
+                let file = if self.is_tree() {
+                    let mut file = tempfile::tempfile()
+                        .map_err(|_| error("IO error creating temporary file"))?;
+                    let mut options = ArchiveOptions::from_config(config);
+                    options.compression = Compression::Stored;
+                    BlobStore::write_archive(self, &options, &mut file)?;
+                    file
+                } else {
+                    File::open(&self.filename)
+                        .map_err(|_| error("IO error while opening template file"))?
+                };
+                let mut zip = ZipArchive::new(BufReader::new(file))
+                    .map_err(|_| error("Error unzipping template file"))?;
+                archive.write(&mut zip, &hide_from_output)
+            }
+        };
+    }
+    /// Check the stored contents of the template against the hashes recorded at save time
+    pub fn verify(&self) -> Result<Integrity> {
+        let actual = if self.is_tree() {
+            BlobStore::hash_entries(self)?
+        } else {
+            let file = File::open(&self.filename)
+                .map_err(|_| error("IO error while opening template file"))?;
+            let mut zip = ZipArchive::new(BufReader::new(file))
+                .map_err(|_| error("Error unzipping template file"))?;
+            ZipUtil::hash_archive(&mut zip)?
+        };
+        return Ok(self.info.check_integrity(&actual));
+    }
+    /// Whether the template is stored in the blob store instead of as a single template file
+    pub fn is_tree(&self) -> bool {
+        return self
+            .filename
+            .extension()
+            .is_some_and(|e| e == globals::FOLDR_TREE_EXTENSION);
+    }
+    /// Write the template as a single self contained template file, which can be shared or fetched
+    pub fn write_archive(&self, config: &Config, output_path: &PathBuf) -> Result<u64> {
+        if self.is_tree() {
+            let output = File::create(output_path)
+                .map_err(|_| error("IO error creating template output file"))?;
+            BlobStore::write_archive(self, &ArchiveOptions::from_config(config), output)?;
+        } else {
+            fs::copy(&self.filename, output_path)
+                .map_err(|_| error("IO error copying template file"))?;
+        }
+        return fs::metadata(output_path)
+            .map(|m| m.len())
+            .map_err(|_| error("Error querying output file size"));
+    }
+    /// Path of a file stored next to the template file, like its signature
+    pub fn sidecar_path(&self, extension: &str) -> PathBuf {
+        let mut path = self.filename.clone().into_os_string();
+        path.push(".");
+        path.push(extension);
+        return path.into();
+    }
+    /// Remove the template file, and any files stored next to it
+    pub fn remove_files(&self) -> Result<()> {
+        fs::remove_file(&self.filename).map_err(|_| error("IO error removing template file"))?;
+        return self.remove_sidecars();
+    }
+    /// Remove the files stored next to the template file, like its signature and source
+    pub fn remove_sidecars(&self) -> Result<()> {
+        for extension in [
+            globals::FOLDR_SIGNATURE_EXTENSION,
+            globals::FOLDR_SOURCE_EXTENSION,
+        ] {
+            let path = self.sidecar_path(extension);
+            if path.exists() {
+                fs::remove_file(path)
+                    .map_err(|_| error("IO error removing file stored next to template"))?;
+            }
+        }
+        return Ok(());
+    }
+    /// Get the sha256 hash of the template file on disk
+    pub fn archive_hash(&self) -> Result<String> {
+        let mut file = File::open(&self.filename)
+            .map_err(|_| error("IO error while opening template file"))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)
+            .map_err(|_| error("IO error while hashing template file"))?;
+        return Ok(format!("{:x}", hasher.finalize()));
     }
     /// Get the hierarchy of a template
-    pub fn get_content_hierarchy(&self) -> TemplateHierarchy {
-        let mut contents =
-            ZipUtil::get_files(self.filename.clone(), vec![FOLDR_MANIFEST_FILE.into()]);
+    pub fn get_content_hierarchy(&self) -> Result<TemplateHierarchy> {
+        let mut contents = if self.is_tree() {
+            BlobStore::get_files(self)?
+        } else {
+            ZipUtil::get_files(self.filename.clone(), vec![FOLDR_MANIFEST_FILE.into()])
+        };
         contents.sort_by_key(|p| p.to_string_lossy().into_owned());
         let root = TemplateHierarchy::from_paths(self.info.name.clone(), &contents);
-        return root;
+        return Ok(root);
     }
 
     /// Create a new template from a directory
@@ -138,6 +286,7 @@ impl Template {
         directory: &PathBuf,
         name: &str,
         iteration: Iteration,
+        options: &ArchiveOptions,
     ) -> Result<Template> {
         if let Ok(exists) = fs::exists(directory) {
             if !exists {
@@ -148,36 +297,30 @@ impl Template {
         }
 
         //load dir into memory TODO make version actually increment
-        let info = TemplateInfo::new(name.into(), iteration);
+        let mut info = TemplateInfo::new(name.into(), iteration);
+        info.preserve_mtime = options.preserve_mtime;
+        info.git = options.git.clone();
         let output_path = info.generate_output_path(config);
-        let extra_files = vec![(
-            PathBuf::from(globals::FOLDR_MANIFEST_FILE),
-            json!(info).to_string(),
-        )];
-        let filesize = ZipUtil::zip_dir(directory, &output_path, extra_files)?;
+        let mut options = options.clone();
+        if config.storage == StorageBackend::Blobs {
+            // The archive is only an intermediate step, compressing it would be wasted work
+            options.compression = Compression::Stored;
+        }
+        let filesize = ZipUtil::zip_dir(directory, &output_path, &mut info, &options)?;
 
-        return Ok(Template {
+        let template = Template {
             info,
-            filename: output_path
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .deref()
-                .into(),
+            filename: output_path,
             filesize: ByteSize::b(filesize),
-        });
+        };
+        if config.storage == StorageBackend::Blobs {
+            return BlobStore::ingest(&template);
+        }
+        return Ok(template);
     }
     pub fn get_existing_by_name(config: &Config, name: &str) -> Result<Option<Template>> {
-        let mut templates = ZipUtil::get_templates(&config.template_dir.expand())?;
-        templates.sort_by_key(|t| t.info.iteration);
-        templates.reverse();
-        for template in templates {
-            if template.info.name == name {
-                return Ok(Some(template));
-            }
-        }
-
-        return Ok(None);
+        let templates = Self::get_existing(config)?;
+        return Ok(templates.into_iter().find(|t| t.info.name == name));
     }
     /// Get an existing template stored in the template directory by name an version number
     pub fn get_existing_by_name_and_iteration(
@@ -185,18 +328,34 @@ impl Template {
         name: &str,
         iteration: Iteration,
     ) -> Result<Option<Template>> {
-        let templates = ZipUtil::get_templates(&config.template_dir.expand())?;
-        for template in templates {
-            if template.info.name == name && template.info.iteration == iteration {
-                return Ok(Some(template));
-            }
-        }
-
-        return Ok(None);
+        let templates = Self::get_existing(config)?;
+        return Ok(templates
+            .into_iter()
+            .find(|t| t.info.name == name && t.info.iteration == iteration));
     }
-    /// Get all existing templates in the template directory
+    /// Get all existing templates in the template stores. The versions of a template are merged across
+    /// stores, when the same version is in more than one store the first store wins
     pub fn get_existing(config: &Config) -> Result<Vec<Template>> {
-        let mut templates = ZipUtil::get_templates(&config.template_dir.expand())?;
+        let mut templates: Vec<Template> = vec![];
+        for (i, store) in config.template_stores().iter().enumerate() {
+            // Shares that are not mounted shouldn't make foldr unusable
+            if i > 0 && !store.is_dir() {
+                eprintln!(
+                    "Warning: template store {} is not available",
+                    store.to_string_lossy()
+                );
+                continue;
+            }
+            let hidden: BTreeSet<(String, Iteration)> = templates
+                .iter()
+                .map(|t| (t.info.name.clone(), t.info.iteration))
+                .collect();
+            templates.extend(
+                ZipUtil::get_templates(store)?
+                    .into_iter()
+                    .filter(|t| !hidden.contains(&(t.info.name.clone(), t.info.iteration))),
+            );
+        }
 
         templates.sort_by_key(|t| t.info.iteration);
         templates.sort_by_key(|t| t.info.name.clone());
@@ -204,24 +363,42 @@ impl Template {
 
         return Ok(templates);
     }
+    /// The template store the template is in
+    pub fn store_dir(&self) -> &Path {
+        return self.filename.parent().unwrap_or(Path::new(""));
+    }
+    /// Fail for templates in one of the read only template_dirs, which can't be changed
+    pub fn ensure_writable(&self, config: &Config) -> Result<()> {
+        if self.store_dir() != config.template_dir.expand() {
+            return Err(error(&format!(
+                "Template {} version {} is in the read only template store {}",
+                self.info.name,
+                self.info.iteration,
+                self.store_dir().to_string_lossy()
+            )));
+        }
+        return Ok(());
+    }
     /// Delete all iterations of a template by name
     pub fn delete_by_name(config: &Config, name: &str) -> Result<bool> {
-        let templates = Self::get_existing(config)?;
-
-        let mut found = false;
-        for template in templates {
-            if template.info.name == name {
-                println!(
-                    "Deleting template file: {}",
-                    template.filename.to_string_lossy()
-                );
-                fs::remove_file(template.filename).unwrap();
+        let templates: Vec<Template> = Self::get_existing(config)?
+            .into_iter()
+            .filter(|t| t.info.name == name)
+            .collect();
+        // Versions can be spread over several stores, so nothing is deleted when one of them is read only
+        for template in &templates {
+            template.ensure_writable(config)?;
+        }
 
-                found = true;
-            }
+        for template in &templates {
+            println!(
+                "Deleting template file: {}",
+                template.filename.to_string_lossy()
+            );
+            template.remove_files()?;
         }
 
-        return Ok(found);
+        return Ok(!templates.is_empty());
     }
     /// Delete a single iteration of a template by name and version number
     pub fn delete_by_name_and_iteration(
@@ -234,11 +411,12 @@ impl Template {
         let mut found = false;
         for template in templates {
             if template.info.name == name && template.info.iteration == iteration {
+                template.ensure_writable(config)?;
                 println!(
                     "Deleting template file: {} version {}",
                     template.info.name, template.info.iteration
                 );
-                fs::remove_file(template.filename).unwrap();
+                template.remove_files()?;
 
                 found = true;
             }
@@ -256,11 +434,23 @@ impl Template {
     ) -> Result<Template> {
         let mut input_zip =
             ZipArchive::new(&mut stream).map_err(|_| error("Template file is corrupt"))?;
-        let info = TemplateInfo::new(name, iteration);
+        ArchiveLimits::from_config(config).check(&mut input_zip)?;
+        let mut info = TemplateInfo::new(name, iteration);
+        let source_info = ZipUtil::read_manifest(&mut input_zip).ok();
+        if let Some(source_info) = &source_info {
+            info.preserve_mtime = source_info.preserve_mtime;
+            info.git = source_info.git.clone();
+        }
+        let mut files = BTreeMap::new();
         let output_file_path = info.generate_output_path(config);
-        let mut output_file = File::create(&output_file_path)
+        let archive_options = ArchiveOptions::from_config(config);
+        // Written next to its final path and moved there once complete, so a failure never leaves a
+        // partial file behind or destroys a template file that is being replaced
+        let mut output_file = tempfile::Builder::new()
+            .prefix(".foldr-store")
+            .tempfile_in(output_file_path.parent().unwrap())
             .map_err(|_| error("IO error creating template output file"))?;
-        let mut output_zip = ZipWriter::new(&mut output_file);
+        let mut output_zip = ZipWriter::new(output_file.as_file_mut());
 
         for i in 0..input_zip.len() {
             let mut file = input_zip.by_index(i).unwrap();
@@ -278,16 +468,56 @@ impl Template {
             if remove_from_output.contains(&file_name) {
                 continue;
             }
-            output_zip
-                .start_file_from_path(file_name.as_path(), SimpleFileOptions::default())
-                .map_err(|_| error("IO Error creating file inside of output template file"))?;
+            let mut options = archive_options.file_options(&file_name);
+            if let Some(mode) = file.unix_mode() {
+                options = options.unix_permissions(mode);
+            }
+            if let Some(mtime) = file.last_modified() {
+                options = options.last_modified_time(mtime);
+            }
+            if file.is_dir() {
+                output_zip
+                    .add_directory_from_path(file_name.as_path(), options)
+                    .map_err(|_| {
+                        error("IO Error creating directory inside of output template file")
+                    })?;
+                continue;
+            }
             let mut buffer = Vec::<u8>::new();
-            file.read_to_end(&mut buffer).map_err(|_| {
-                error(&format!(
-                    "IO Error reading file {}",
-                    file_name.to_string_lossy()
-                ))
-            })?;
+            let size = file.size();
+            LimitedReader::new(&mut file, size)
+                .read_to_end(&mut buffer)
+                .map_err(|e| {
+                    error(&format!(
+                        "IO Error reading file {}: {}",
+                        file_name.to_string_lossy(),
+                        e
+                    ))
+                })?;
+            files.insert(
+                file_name.to_string_lossy().into_owned(),
+                format!("{:x}", Sha256::digest(&buffer)),
+            );
+            if file.is_symlink() {
+                output_zip
+                    .add_symlink(
+                        ZipUtil::entry_name(&file_name),
+                        String::from_utf8_lossy(&buffer),
+                        options,
+                    )
+                    .map_err(|_| {
+                        error("IO Error creating symlink inside of output template file")
+                    })?;
+                continue;
+            }
+            output_zip
+                .start_file_from_path(file_name.as_path(), options)
+                .map_err(|e| {
+                    error(&format!(
+                        "IO Error creating file inside of output template file: {}",
+                        e
+                    ))
+                })?;
             output_zip.write_all(&buffer).map_err(|_| {
                 error(&format!(
                     "IO Error writing file {} from template",
@@ -296,6 +526,15 @@ impl Template {
             })?;
         }
 
+        if let Some(source_info) = &source_info
+            && let Integrity::Invalid(problems) = source_info.check_integrity(&files)
+        {
+            return Err(error(&format!(
+                "Template file does not match the hashes in its manifest:\n{}",
+                problems.join("\n")
+            )));
+        }
+        info.set_files(files);
         output_zip
             .start_file(FOLDR_MANIFEST_FILE, SimpleFileOptions::default())
             .map_err(|_| error("IO Error creating manifest file in output template"))?;
@@ -307,38 +546,124 @@ impl Template {
             .finish()
             .map_err(|_| error("Failure to compress template file on disk"))?;
 
-        println!("Copied template data to disk");
         let size = output_file
+            .as_file()
             .metadata()
-            .map_err(|_| error("Failure to get template metadata. Template still written."))?
+            .map_err(|_| error("Failure to get template metadata"))?
             .len();
-        return Ok(Template {
+        output_file
+            .persist(&output_file_path)
+            .map_err(|_| error("IO error moving template file into place"))?;
+        println!("Copied template data to disk");
+        let template = Template {
             info,
             filename: output_file_path.clone(),
             filesize: ByteSize::b(size),
-        });
+        };
+        if config.storage == StorageBackend::Blobs {
+            return BlobStore::ingest(&template);
+        }
+        return Ok(template);
     }
     pub fn spawn_from_stream<R: Read + Seek>(
         config: &Config,
-        spawn_path: &PathBuf,
+        target: &SpawnTarget,
         mut stream: R,
         remove_from_output: Vec<PathBuf>,
     ) -> Result<()> {
-        return ZipUtil::unzip_from_stream(spawn_path, &mut stream, remove_from_output);
+        let limits = ArchiveLimits::from_config(config);
+        let mut zip =
+            ZipArchive::new(&mut stream).map_err(|_| error("Template file is corrupt"))?;
+        limits.check(&mut zip)?;
+        if let Ok(info) = ZipUtil::read_manifest(&mut zip)
+            && let Integrity::Invalid(problems) =
+                info.check_integrity(&ZipUtil::hash_archive(&mut zip)?)
+        {
+            return Err(error(&format!(
+                "Template file does not match the hashes in its manifest:\n{}",
+                problems.join("\n")
+            )));
+        }
+        stream
+            .rewind()
+            .map_err(|_| error("Error reading template stream"))?;
+        return match target {
+            SpawnTarget::Directory(spawn_path) => {
+                ZipUtil::unzip_from_stream(spawn_path, &mut stream, remove_from_output, &limits)
+            }
+            SpawnTarget::Archive(archive) => {
+                let mut zip =
+                    ZipArchive::new(&mut stream).map_err(|_| error("Template file is corrupt"))?;
+                archive.write(&mut zip, &remove_from_output)
+            }
+        };
     }
 }
 impl TemplateInfo {
     pub fn new(name: String, iteration: Iteration) -> Self {
-        return Self { name, iteration };
+        return Self {
+            name,
+            iteration,
+            preserve_mtime: false,
+            files: BTreeMap::new(),
+            digest: None,
+            git: None,
+        };
+    }
+    /// Record the file hashes of the template, together with the digest over all of them
+    pub fn set_files(&mut self, files: BTreeMap<String, String>) {
+        self.digest = Some(Self::compute_digest(&files));
+        self.files = files;
+    }
+    /// Compute the digest of a template from its file hashes. The digest only depends on the contents,
+    /// so it stays the same when a template is stored under a different name or iteration
+    pub fn compute_digest(files: &BTreeMap<String, String>) -> String {
+        let mut hasher = Sha256::new();
+        for (path, hash) in files {
+            hasher.update(path.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(hash.as_bytes());
+            hasher.update(b"\n");
+        }
+        return format!("{:x}", hasher.finalize());
+    }
+    /// Compare the actual file hashes of a template to the hashes recorded in the manifest
+    pub fn check_integrity(&self, actual: &BTreeMap<String, String>) -> Integrity {
+        let Some(digest) = &self.digest else {
+            return Integrity::Unrecorded;
+        };
+        let mut problems = vec![];
+        if *digest != Self::compute_digest(&self.files) {
+            problems.push("template digest does not match the recorded file hashes".to_string());
+        }
+        for (path, hash) in &self.files {
+            match actual.get(path) {
+                None => problems.push(format!("{} is missing", path)),
+                Some(actual_hash) if actual_hash != hash => {
+                    problems.push(format!("{} does not match its recorded hash", path))
+                }
+                Some(_) => {}
+            }
+        }
+        for path in actual.keys() {
+            if !self.files.contains_key(path) {
+                problems.push(format!("{} is not part of the recorded template", path));
+            }
+        }
+        if problems.is_empty() {
+            return Integrity::Valid;
+        }
+        return Integrity::Invalid(problems);
     }
     // TODO error handling
     pub fn generate_output_path(&self, config: &Config) -> PathBuf {
         let output_dir = &config.template_dir.expand();
         let output_file = format!(
-            "{}/{}-{}.foldr",
+            "{}/{:x}-{}.{}",
             output_dir.to_string_lossy(),
-            format!("{:x}", Sha256::digest(&self.name.as_bytes())),
-            self.iteration
+            Sha256::digest(self.name.as_bytes()),
+            self.iteration,
+            globals::FOLDR_TEMPLATE_EXTENSION
         )
         .into();
         fs::create_dir_all(output_dir).unwrap();
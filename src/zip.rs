@@ -1,66 +1,319 @@
 use std::{
+    collections::{BTreeMap, BTreeSet},
     fs::{self, File},
     io::{self, BufReader, Read, Seek, Write},
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
 };
 
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
 use walkdir::WalkDir;
-use zip::{ZipArchive, ZipWriter, write::SimpleFileOptions};
+use zip::{CompressionMethod, DateTime, ZipArchive, ZipWriter, write::SimpleFileOptions};
 
 use crate::{
+    blobs::{BlobStore, HashingWriter},
     commands::command::{Result, error},
+    config::Config,
+    git::GitProvenance,
     globals,
-    templates::Template,
+    templates::{Template, TemplateInfo},
 };
 
 pub struct ZipUtil;
 
+/// How symbolic links inside of a directory are handled when saving it as a template
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum SymlinkMode {
+    /// Store the link itself, so it is recreated as a link when spawning
+    #[default]
+    Keep,
+    /// Store the file or directory the link points to
+    Dereference,
+}
+
+/// Compression method used for the files inside of a template file
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Stored,
+    #[default]
+    Deflate,
+    Zstd,
+    Bzip2,
+}
+
+impl Compression {
+    fn method(&self) -> CompressionMethod {
+        return match self {
+            Compression::Stored => CompressionMethod::Stored,
+            Compression::Deflate => CompressionMethod::Deflated,
+            Compression::Zstd => CompressionMethod::Zstd,
+            Compression::Bzip2 => CompressionMethod::Bzip2,
+        };
+    }
+}
+
+/// Options used when compressing a directory into a template file
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveOptions {
+    pub symlinks: SymlinkMode,
+    pub compression: Compression,
+    pub compression_level: Option<i64>,
+    /// Extensions of files that are stored without compression, because they are already compressed
+    pub store_extensions: Vec<String>,
+    /// Record the modification time of every file so it can be restored when spawning
+    pub preserve_mtime: bool,
+    /// Write a fixed timestamp and a sorted entry order, so the same directory saved under the same name
+    /// and version always results in the same archive
+    pub reproducible: bool,
+    /// When set, only these paths relative to the directory are saved, together with the directories
+    /// leading to them
+    pub include: Option<BTreeSet<PathBuf>>,
+    /// The git commit the directory was saved from, recorded in the manifest
+    pub git: Option<GitProvenance>,
+}
+
+impl ArchiveOptions {
+    /// Get the default archive options as set in the configuration
+    pub fn from_config(config: &Config) -> Self {
+        return Self {
+            compression: config.compression,
+            compression_level: config.compression_level,
+            store_extensions: config.store_extensions.clone(),
+            ..Default::default()
+        };
+    }
+
+    /// Get the zip options for a file, storing already compressed files without compressing them again
+    pub fn file_options(&self, path: &Path) -> SimpleFileOptions {
+        let already_compressed = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .is_some_and(|e| self.store_extensions.contains(&e));
+        let mut options = SimpleFileOptions::default();
+        if self.reproducible {
+            options = options.last_modified_time(DateTime::default());
+        }
+        if already_compressed || self.compression == Compression::Stored {
+            return options.compression_method(CompressionMethod::Stored);
+        }
+        return options
+            .compression_method(self.compression.method())
+            .compression_level(self.compression_level);
+    }
+}
+
+/// Limits on the contents of archives from untrusted sources, so a hostile template can't fill the disk
+/// or keep foldr busy. Checked against the central directory before any entry is read
+#[derive(Clone, Debug)]
+pub struct ArchiveLimits {
+    pub max_size: u64,
+    pub max_entries: usize,
+    pub max_depth: usize,
+    pub max_ratio: u64,
+}
+
+/// Entries smaller than this are not checked against the compression ratio limit. Small files
+/// of repeated content compress very well, but can't exhaust anything
+const RATIO_CHECK_MIN_SIZE: u64 = 1024 * 1024;
+
+impl ArchiveLimits {
+    /// Get the archive limits as set in the configuration
+    pub fn from_config(config: &Config) -> Self {
+        return Self {
+            max_size: config.max_template_size,
+            max_entries: config.max_template_entries,
+            max_depth: config.max_template_depth,
+            max_ratio: config.max_compression_ratio,
+        };
+    }
+
+    /// Check the sizes, entry count, path depth and compression ratios recorded in an archive
+    pub fn check<R: Read + Seek>(&self, zip: &mut ZipArchive<R>) -> Result<()> {
+        if self.max_entries > 0 && zip.len() > self.max_entries {
+            return Err(error(&format!(
+                "Template contains {} entries, more than the limit of {}",
+                zip.len(),
+                self.max_entries
+            )));
+        }
+        let mut total: u64 = 0;
+        for i in 0..zip.len() {
+            let file = zip
+                .by_index_raw(i)
+                .map_err(|_| error("Error reading file from template"))?;
+            let depth = Path::new(file.name()).components().count();
+            if self.max_depth > 0 && depth > self.max_depth {
+                return Err(error(&format!(
+                    "Template contains {} nested {} levels deep, more than the limit of {}",
+                    file.name(),
+                    depth,
+                    self.max_depth
+                )));
+            }
+            if self.max_ratio > 0
+                && file.size() >= RATIO_CHECK_MIN_SIZE
+                && file.size() / file.compressed_size().max(1) > self.max_ratio
+            {
+                return Err(error(&format!(
+                    "Template contains {} with a compression ratio over the limit of {}. Template might be harmful",
+                    file.name(),
+                    self.max_ratio
+                )));
+            }
+            total = total.saturating_add(file.size());
+            if self.max_size > 0 && total > self.max_size {
+                return Err(error(&format!(
+                    "Template unpacks to more than the limit of {}",
+                    bytesize::ByteSize::b(self.max_size)
+                )));
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Reader that fails once more bytes are read than an archive entry recorded, so the limits checked
+/// against the recorded sizes also hold for the data that is actually extracted
+pub struct LimitedReader<R: Read> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, limit: u64) -> Self {
+        return Self {
+            inner,
+            remaining: limit,
+        };
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            let mut probe = [0u8; 1];
+            if self.inner.read(&mut probe)? == 0 {
+                return Ok(0);
+            }
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "entry is larger than its recorded size. Template might be harmful",
+            ));
+        }
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read as u64;
+        return Ok(read);
+    }
+}
+
 impl ZipUtil {
     pub fn zip_dir(
         input_dir: &PathBuf,
         output_file: &PathBuf,
-        extra_files: Vec<(PathBuf, String)>,
+        info: &mut TemplateInfo,
+        archive_options: &ArchiveOptions,
     ) -> Result<u64> {
         let file = File::create(output_file).unwrap();
         let mut writer = ZipWriter::new(file);
-        let options = SimpleFileOptions::default();
-
-        // Add extra files
-        for entry in extra_files {
-            let path = entry.0;
-            let content = entry.1;
+        let mut files = BTreeMap::new();
 
-            writer
-                .start_file(path.to_string_lossy(), options)
-                .map_err(|_| error("Error writing template manifest file"))?;
-            writer
-                .write_all(content.as_bytes())
-                .map_err(|_| error("Error writing template manifest file"))?;
-        }
+        // Directories leading to included paths, which have to be walked to reach them
+        let parents: Option<BTreeSet<&Path>> = archive_options
+            .include
+            .as_ref()
+            .map(|include| include.iter().flat_map(|path| path.ancestors()).collect());
 
-        // Add all files and folders recursively
-        for entry in WalkDir::new(input_dir) {
+        // Add all files and folders recursively, sorted so the entry order doesn't depend on the
+        // order the file system lists them in
+        let walker = WalkDir::new(input_dir)
+            .follow_links(archive_options.symlinks == SymlinkMode::Dereference)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| {
+                let relative_path = entry.path().strip_prefix(input_dir).unwrap();
+                return archive_options.include.as_ref().is_none_or(|include| {
+                    include.contains(relative_path)
+                        || parents
+                            .as_ref()
+                            .is_some_and(|parents| parents.contains(relative_path))
+                });
+            });
+        for entry in walker {
             let entry =
                 entry.map_err(|_| error("Something went wrong traversing the template file"))?;
             let path = entry.path();
-            if path.is_file() {
-                let relative_path = path.strip_prefix(input_dir).unwrap();
+            let relative_path = path.strip_prefix(input_dir).unwrap();
+            if relative_path.as_os_str().is_empty() {
+                continue;
+            }
+            let metadata = entry
+                .metadata()
+                .map_err(|_| error("Error reading file metadata while saving template"))?;
+            let mut file_options = archive_options.file_options(relative_path);
+            if let Some(mode) = Self::unix_mode(&metadata) {
+                file_options = file_options.unix_permissions(mode);
+            }
+            if archive_options.preserve_mtime
+                && let Some(mtime) = metadata.modified().ok().and_then(Self::to_zip_time)
+            {
+                file_options = file_options.last_modified_time(mtime);
+            }
+
+            if entry.path_is_symlink() && archive_options.symlinks == SymlinkMode::Keep {
+                let target = fs::read_link(path)
+                    .map_err(|_| error("Error reading symlink target while saving template"))?;
+                files.insert(
+                    relative_path.to_string_lossy().into_owned(),
+                    format!("{:x}", Sha256::digest(target.to_string_lossy().as_bytes())),
+                );
+                writer
+                    .add_symlink(
+                        Self::entry_name(relative_path),
+                        target.to_string_lossy(),
+                        file_options,
+                    )
+                    .map_err(|_| error("Error writing symlink in template file"))?;
+            } else if metadata.is_file() {
                 writer
-                    .start_file(relative_path.to_string_lossy(), options)
-                    .unwrap();
+                    .start_file_from_path(relative_path, file_options)
+                    .map_err(|e| error(&format!("Error creating file in template file: {}", e)))?;
 
-                let mut f = File::open(path).unwrap();
-                std::io::copy(&mut f, &mut writer).unwrap();
-            } else if path.is_dir() {
+                let mut f = File::open(path)
+                    .map_err(|_| error("IO error while reading file for template"))?;
+                let mut hashing_writer = HashingWriter::new(&mut writer);
+                std::io::copy(&mut f, &mut hashing_writer)
+                    .map_err(|_| error("IO error while writing file to template"))?;
+                files.insert(
+                    relative_path.to_string_lossy().into_owned(),
+                    hashing_writer.hash(),
+                );
+            } else if metadata.is_dir() {
                 // Zip doesn't require explicit folder entries, but it's OK to include them
-                let relative_path = path.strip_prefix(input_dir).unwrap();
-                let folder_name = format!("{}/", relative_path.to_string_lossy());
                 writer
-                    .add_directory(folder_name, options)
+                    .add_directory_from_path(relative_path, file_options)
                     .map_err(|_| error("Error creating directory in template file"))?;
             }
         }
 
+        // The manifest goes last, so it can record the hashes of everything before it
+        info.set_files(files);
+        writer
+            .start_file(
+                globals::FOLDR_MANIFEST_FILE,
+                archive_options.file_options(Path::new(globals::FOLDR_MANIFEST_FILE)),
+            )
+            .map_err(|_| error("Error writing template manifest file"))?;
+        writer
+            .write_all(serde_json::to_string(info).unwrap().as_bytes())
+            .map_err(|_| error("Error writing template manifest file"))?;
+
         let result = writer.finish().unwrap();
         return Ok(result
             .metadata()
@@ -72,9 +325,17 @@ impl ZipUtil {
             return Err(error("Template directory points to non directory path"));
         }
         let mut templates: Vec<Template> = vec![];
-        for entry in WalkDir::new(template_dir).into_iter().skip(1) {
+        for entry in WalkDir::new(template_dir).min_depth(1).max_depth(1) {
             let entry = entry.map_err(|_| error("Error traversing template file"))?;
             let path = entry.path();
+            let extension = path.extension().unwrap_or_default();
+            if extension == globals::FOLDR_TREE_EXTENSION {
+                templates.push(BlobStore::read_template(&path.to_path_buf())?);
+                continue;
+            }
+            if extension != globals::FOLDR_TEMPLATE_EXTENSION {
+                continue;
+            }
             let file =
                 File::open(path).map_err(|_| error("IO error while opening template file"))?;
             let size = file
@@ -83,16 +344,8 @@ impl ZipUtil {
                 .len();
             let mut zip = ZipArchive::new(BufReader::new(file))
                 .map_err(|_| error("Error unzipping template file"))?;
-            let mut manifest_file = zip
-                .by_name(&globals::FOLDR_MANIFEST_FILE)
-                .map_err(|_| error("Template file contains no Manifest"))?;
-            let mut manifest_content = String::new();
-            manifest_file
-                .read_to_string(&mut manifest_content)
-                .map_err(|_| error("Error reading manifest file from template"))?;
             templates.push(Template {
-                info: serde_json::from_str(&manifest_content)
-                    .map_err(|_| error("Template manifest file corrupt"))?,
+                info: Self::read_manifest(&mut zip)?,
                 filename: path.to_owned(),
                 filesize: bytesize::ByteSize::b(size),
             });
@@ -100,64 +353,290 @@ impl ZipUtil {
 
         return Ok(templates);
     }
-    pub fn unzip(template: &Template, path: &PathBuf, hide_from_output: Vec<PathBuf>) {
-        let zip_to_open = &template.filename;
-        let file = File::open(zip_to_open).unwrap();
-        let mut zip = ZipArchive::new(file).unwrap();
-
+    /// Hash the contents of every file and symlink in an archive, leaving out the manifest
+    pub fn hash_archive<R: Read + Seek>(
+        zip: &mut ZipArchive<R>,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut files = BTreeMap::new();
         for i in 0..zip.len() {
-            let mut file = zip.by_index(i).unwrap();
-            if hide_from_output
-                .iter()
-                .find(|p| p.to_string_lossy() == file.name())
-                .iter()
-                .len()
-                > 0
-            {
+            let mut file = zip
+                .by_index(i)
+                .map_err(|_| error("Error reading file from template"))?;
+            let Some(path) = file.enclosed_name() else {
+                return Err(error(
+                    "Template file contains files trying to escape its path. Template might be harmful",
+                ));
+            };
+            if file.is_dir() || path == Path::new(globals::FOLDR_MANIFEST_FILE) {
                 continue;
             }
-            let out_path = path.join(file.name());
-            if file.name().ends_with("/") {
-                fs::create_dir_all(&out_path).unwrap();
-            } else {
-                if let Some(parent) = out_path.parent() {
-                    fs::create_dir_all(parent).unwrap();
-                }
-                let mut out_file = File::create(&out_path).unwrap();
-                io::copy(&mut file, &mut out_file).unwrap();
-            }
+            let mut hasher = Sha256::new();
+            let size = file.size();
+            io::copy(&mut LimitedReader::new(&mut file, size), &mut hasher).map_err(|e| {
+                error(&format!(
+                    "IO error reading {} from template: {}",
+                    path.to_string_lossy(),
+                    e
+                ))
+            })?;
+            files.insert(
+                path.to_string_lossy().into_owned(),
+                format!("{:x}", hasher.finalize()),
+            );
         }
+        return Ok(files);
+    }
+
+    /// Read the template manifest stored inside of an archive
+    pub fn read_manifest<R: Read + Seek>(zip: &mut ZipArchive<R>) -> Result<TemplateInfo> {
+        let mut manifest_file = zip
+            .by_name(globals::FOLDR_MANIFEST_FILE)
+            .map_err(|_| error("Template file contains no Manifest"))?;
+        let mut manifest_content = String::new();
+        let size = manifest_file.size();
+        LimitedReader::new(&mut manifest_file, size)
+            .read_to_string(&mut manifest_content)
+            .map_err(|_| error("Error reading manifest file from template"))?;
+        return serde_json::from_str(&manifest_content)
+            .map_err(|_| error("Template manifest file corrupt"));
+    }
+
+    pub fn unzip(
+        template: &Template,
+        path: &PathBuf,
+        hide_from_output: Vec<PathBuf>,
+    ) -> Result<()> {
+        let file = File::open(&template.filename)
+            .map_err(|_| error("IO error while opening template file"))?;
+        let mut zip = ZipArchive::new(BufReader::new(file))
+            .map_err(|_| error("Error unzipping template file"))?;
+        return Self::extract(&mut zip, path, hide_from_output);
     }
 
     pub fn unzip_from_stream<R: Read + Seek>(
         spawn_path: &PathBuf,
         stream: R,
         hide_from_output: Vec<PathBuf>,
+        limits: &ArchiveLimits,
+    ) -> Result<()> {
+        let mut zip = ZipArchive::new(stream).map_err(|_| error("Template file is corrupt"))?;
+        limits.check(&mut zip)?;
+        return Self::extract(&mut zip, spawn_path, hide_from_output);
+    }
+
+    /// Extract every entry of an archive into [`spawn_path`], restoring unix permissions and symlinks
+    fn extract<R: Read + Seek>(
+        zip: &mut ZipArchive<R>,
+        spawn_path: &Path,
+        hide_from_output: Vec<PathBuf>,
     ) -> Result<()> {
-        let mut zip = ZipArchive::new(stream).unwrap();
+        let mut symlinks: Vec<(PathBuf, PathBuf)> = vec![];
+        let mut directories: Vec<(PathBuf, Option<u32>, Option<SystemTime>)> = vec![];
+        let restore_mtimes = Self::read_manifest(zip).is_ok_and(|info| info.preserve_mtime);
 
         for i in 0..zip.len() {
-            let mut file = zip.by_index(i).unwrap();
-            if hide_from_output
-                .iter()
-                .find(|p| p.to_string_lossy() == file.name())
-                .iter()
-                .len()
-                > 0
-            {
+            let mut file = zip
+                .by_index(i)
+                .map_err(|_| error("Error reading file from template"))?;
+            let Some(relative_path) = file.enclosed_name() else {
+                return Err(error(
+                    "Template file contains files trying to escape its path. Template might be harmful",
+                ));
+            };
+            if relative_path.as_os_str().is_empty() || hide_from_output.contains(&relative_path) {
                 continue;
             }
-            let out_path = spawn_path.join(file.name());
-            if file.name().ends_with("/") {
-                fs::create_dir_all(&out_path).unwrap();
+            let out_path = spawn_path.join(&relative_path);
+            let mtime = if restore_mtimes {
+                file.last_modified().and_then(Self::to_system_time)
             } else {
-                if let Some(parent) = out_path.parent() {
-                    fs::create_dir_all(parent).unwrap();
-                }
-                let mut out_file = File::create(&out_path).unwrap();
-                io::copy(&mut file, &mut out_file).unwrap();
+                None
+            };
+            if file.is_dir() {
+                fs::create_dir_all(&out_path)
+                    .map_err(|_| error("IO error creating directory from template"))?;
+                directories.push((out_path, file.unix_mode(), mtime));
+                continue;
+            }
+            if let Some(parent) = out_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|_| error("IO error creating directory from template"))?;
+            }
+            let file_is_symlink = file.is_symlink();
+            let mode = file.unix_mode();
+            let size = file.size();
+            let mut reader = LimitedReader::new(&mut file, size);
+            if file_is_symlink {
+                let mut target = String::new();
+                reader
+                    .read_to_string(&mut target)
+                    .map_err(|e| error(&format!("Error reading symlink from template: {}", e)))?;
+                symlinks.push((out_path, target.into()));
+                continue;
+            }
+            Self::write_file(&mut reader, &out_path, mode, mtime)?;
+        }
+
+        return Self::finish_extract(spawn_path, symlinks, directories);
+    }
+
+    /// Write a single extracted file to disk, restoring its permissions and modification time
+    pub fn write_file<R: Read>(
+        reader: &mut R,
+        out_path: &PathBuf,
+        mode: Option<u32>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        let mut out_file = File::create(out_path).map_err(|_| {
+            error(&format!(
+                "IO error creating file {}",
+                out_path.to_string_lossy()
+            ))
+        })?;
+        io::copy(reader, &mut out_file).map_err(|e| {
+            error(&format!(
+                "IO error writing file {} from template: {}",
+                out_path.to_string_lossy(),
+                e
+            ))
+        })?;
+        if let Some(mtime) = mtime {
+            out_file
+                .set_modified(mtime)
+                .map_err(|_| error("IO error restoring file modification time"))?;
+        }
+        if let Some(mode) = mode {
+            Self::set_unix_mode(out_path, mode)?;
+        }
+        return Ok(());
+    }
+
+    /// Create the symlinks and apply the directory permissions and times collected while extracting.
+    /// Links and directory permissions are applied last, so a link or a read-only directory
+    /// can never redirect or block the files extracted after it
+    pub fn finish_extract(
+        spawn_path: &Path,
+        symlinks: Vec<(PathBuf, PathBuf)>,
+        directories: Vec<(PathBuf, Option<u32>, Option<SystemTime>)>,
+    ) -> Result<()> {
+        for (link, target) in symlinks {
+            Self::check_symlink_target(spawn_path, &link, &target)?;
+            Self::create_symlink(&target, &link)?;
+        }
+        for (directory, mode, mtime) in directories.into_iter().rev() {
+            if let Some(mtime) = mtime {
+                File::open(&directory)
+                    .and_then(|d| d.set_modified(mtime))
+                    .map_err(|_| error("IO error restoring directory modification time"))?;
+            }
+            if let Some(mode) = mode {
+                Self::set_unix_mode(&directory, mode)?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Name of the archive entry for [`path`]. Symlinks are added with add_symlink instead of
+    /// add_symlink_from_path, which would normalize their targets and drop any .. in them
+    pub fn entry_name(path: &Path) -> String {
+        return path.to_string_lossy().replace('\\', "/");
+    }
+
+    /// Refuse symlinks that point outside of [`spawn_path`], so files written through them later
+    /// can't escape it. Targets have to be relative, with any .. components at the start, and may
+    /// not climb above the spawn path from where the link actually ends up
+    fn check_symlink_target(spawn_path: &Path, link: &Path, target: &Path) -> Result<()> {
+        let escapes = || {
+            error(&format!(
+                "Template contains a symlink {} pointing outside of the spawn directory. Template might be harmful",
+                link.strip_prefix(spawn_path)
+                    .unwrap_or(link)
+                    .to_string_lossy()
+            ))
+        };
+        let mut climbs = 0;
+        let mut descended = false;
+        for component in target.components() {
+            match component {
+                Component::ParentDir if !descended => climbs += 1,
+                Component::Normal(_) => descended = true,
+                Component::CurDir => {}
+                _ => return Err(escapes()),
             }
         }
+        // Measured on the real paths, since a directory above the link can be a symlink itself
+        let root = spawn_path.canonicalize().map_err(|_| escapes())?;
+        let parent = link
+            .parent()
+            .and_then(|p| p.canonicalize().ok())
+            .ok_or_else(escapes)?;
+        let Ok(depth) = parent.strip_prefix(&root).map(|p| p.components().count()) else {
+            return Err(escapes());
+        };
+        if climbs > depth {
+            return Err(escapes());
+        }
+        return Ok(());
+    }
+
+    pub fn to_zip_time(time: SystemTime) -> Option<DateTime> {
+        return DateTime::try_from(OffsetDateTime::from(time)).ok();
+    }
+    pub fn to_system_time(time: DateTime) -> Option<SystemTime> {
+        return OffsetDateTime::try_from(time).ok().map(SystemTime::from);
+    }
+
+    #[cfg(unix)]
+    pub fn unix_mode(metadata: &fs::Metadata) -> Option<u32> {
+        use std::os::unix::fs::PermissionsExt;
+        return Some(metadata.permissions().mode());
+    }
+    #[cfg(not(unix))]
+    pub fn unix_mode(_metadata: &fs::Metadata) -> Option<u32> {
+        return None;
+    }
+
+    /// Apply the permission bits of an extracted entry. Setuid, setgid and sticky bits are dropped,
+    /// since they come from the archive and aren't covered by the template digest or signature
+    #[cfg(unix)]
+    fn set_unix_mode(path: &PathBuf, mode: u32) -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        return fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)).map_err(|_| {
+            error(&format!(
+                "IO error setting permissions of {}",
+                path.to_string_lossy()
+            ))
+        });
+    }
+    #[cfg(not(unix))]
+    fn set_unix_mode(_path: &PathBuf, _mode: u32) -> Result<()> {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    fn create_symlink(target: &PathBuf, link: &PathBuf) -> Result<()> {
+        if link.symlink_metadata().is_ok() {
+            fs::remove_file(link).map_err(|_| {
+                error(&format!(
+                    "IO error replacing existing file {}",
+                    link.to_string_lossy()
+                ))
+            })?;
+        }
+        return std::os::unix::fs::symlink(target, link).map_err(|_| {
+            error(&format!(
+                "IO error creating symlink {}",
+                link.to_string_lossy()
+            ))
+        });
+    }
+    #[cfg(not(unix))]
+    fn create_symlink(target: &PathBuf, link: &PathBuf) -> Result<()> {
+        println!(
+            "Skipping symlink {} -> {}, symlinks are not supported on this platform",
+            link.to_string_lossy(),
+            target.to_string_lossy()
+        );
         return Ok(());
     }
     pub fn get_files(filename: PathBuf, hide_from_output: Vec<String>) -> Vec<PathBuf> {
@@ -183,8 +662,94 @@ impl ZipUtil {
             ) {
                 continue;
             }
-            file_names.push(file.enclosed_name().expect("Dangerous zip file").into());
+            file_names.push(file.enclosed_name().expect("Dangerous zip file"));
         }
         return file_names;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Zip holding a single file with [`mode`]. The zip writer keeps only the permission bits, so
+    /// the mode is patched into the central directory afterwards
+    fn zip_with_mode(name: &str, mode: u32) -> Vec<u8> {
+        let mut writer = ZipWriter::new(io::Cursor::new(Vec::new()));
+        writer
+            .start_file(name, SimpleFileOptions::default().unix_permissions(0o755))
+            .unwrap();
+        writer.write_all(b"#!/bin/sh\n").unwrap();
+        let mut bytes = writer.finish().unwrap().into_inner();
+        let header = bytes
+            .windows(4)
+            .position(|w| w == [0x50, 0x4b, 0x01, 0x02])
+            .unwrap();
+        let attributes = (0o100000 | mode) << 16;
+        bytes[header + 38..header + 42].copy_from_slice(&attributes.to_le_bytes());
+        return bytes;
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spawn_drops_setuid_bit() {
+        use std::os::unix::fs::PermissionsExt;
+        let spawn_path = tempfile::tempdir().unwrap();
+        let limits = ArchiveLimits {
+            max_size: 0,
+            max_entries: 0,
+            max_depth: 0,
+            max_ratio: 0,
+        };
+        ZipUtil::unzip_from_stream(
+            &spawn_path.path().to_path_buf(),
+            io::Cursor::new(zip_with_mode("run.sh", 0o4755)),
+            vec![],
+            &limits,
+        )
+        .unwrap();
+        let mode = fs::metadata(spawn_path.path().join("run.sh"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o755);
+    }
+
+    #[test]
+    fn symlink_targets_outside_spawn_path_are_rejected() {
+        let spawn_path = tempfile::tempdir().unwrap();
+        let root = spawn_path.path();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        let link = root.join("sub").join("link");
+        for target in [
+            "/etc/passwd",
+            "../../outside",
+            "a/../..",
+            "../a/../../outside",
+        ] {
+            assert!(
+                ZipUtil::check_symlink_target(root, &link, Path::new(target)).is_err(),
+                "{} was accepted",
+                target
+            );
+        }
+        for target in ["../file", "other", "./other", "../sub/other"] {
+            assert!(
+                ZipUtil::check_symlink_target(root, &link, Path::new(target)).is_ok(),
+                "{} was rejected",
+                target
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_below_linked_directory_is_checked_on_the_real_path() {
+        let spawn_path = tempfile::tempdir().unwrap();
+        let root = spawn_path.path();
+        // "up" points at the spawn path itself, so up/link really is a link at the top level
+        std::os::unix::fs::symlink(".", root.join("up")).unwrap();
+        let link = root.join("up").join("link");
+        assert!(ZipUtil::check_symlink_target(root, &link, Path::new("../outside")).is_err());
+    }
+}